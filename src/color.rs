@@ -1,5 +1,3 @@
-use std::fmt;
-
 use crate::vec3::Vec3;
 
 #[derive(Debug, Clone, Copy)]
@@ -53,17 +51,18 @@ impl Color {
         Self { r, g, b }
     }
 
-    pub fn ppm(self) -> PpmColor {
-        PpmColor(self)
+    pub fn channels(self) -> [u8; 3] {
+        [self.r, self.g, self.b]
     }
-}
 
-/// Wrapper for displaying a Color in PPM format.
-pub struct PpmColor(Color);
+    /// Each channel in `0..=1`, for an [`Image`](crate::image::Image).
+    pub fn to_rgb(self) -> [f32; 3] {
+        self.channels().map(|c| c as f32 / 255.)
+    }
 
-impl fmt::Display for PpmColor {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let Color { r, g, b } = self.0;
-        write!(f, "{r} {g} {b}")
+    /// Rounds to the nearest color, clamping if necessary.
+    pub fn from_rgb(rgb: [f32; 3]) -> Self {
+        let [r, g, b] = rgb.map(|c| (c.clamp(0., 1.) * 255.).round() as u8);
+        Self { r, g, b }
     }
 }
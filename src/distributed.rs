@@ -0,0 +1,604 @@
+//! Renders a frame on several processes, possibly on other machines: a
+//! coordinator splits it into tiles and hands them out over TCP to workers.
+//!
+//! The coordinator connects to each worker and sends it a [`Job`]; the
+//! worker loads the scene and answers with a status byte. Then, for as long
+//! as there are tiles, the coordinator sends one [`Region`] at a time and the
+//! worker answers with a status byte and the tile's pixels, as little-endian
+//! [`f32`]s. A status of 1 is followed by an error message instead. The
+//! coordinator hangs up when it's done.
+//!
+//! Workers render exactly the pixels a single process would, so the image
+//! doesn't depend on how many workers there are, or which tile went where.
+//! A worker that can't be reached, fails part way through, or takes longer
+//! than the [`Timeouts`], gives its tile back for another to render.
+
+use std::{
+    fmt,
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    error::{Error, Result},
+    image::Image,
+    scene::{Integrator, Region, RenderSettings},
+    Scene,
+};
+
+const MAGIC: &[u8; 8] = b"RTWORK1\n";
+
+const OK: u8 = 0;
+const FAILED: u8 = 1;
+
+/// What a worker needs to render tiles of a frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Job {
+    /// The scene as SDL, with includes resolved, since workers may not have
+    /// its files.
+    pub scene: String,
+    /// The resolution to render at, which may differ from the scene's.
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub seed: u64,
+    pub integrator: Integrator,
+}
+
+impl Job {
+    fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        write_string(out, &self.scene)?;
+        for n in [self.width, self.height, self.samples] {
+            out.write_all(&n.to_le_bytes())?;
+        }
+        out.write_all(&self.seed.to_le_bytes())?;
+        write_string(out, &self.integrator.to_string())?;
+        out.flush()
+    }
+
+    fn read(input: &mut impl Read) -> io::Result<Self> {
+        if &read_array(input)? != MAGIC {
+            return Err(invalid("not a render job"));
+        }
+        let scene = read_string(input)?;
+        let width = read_u32(input)?;
+        let height = read_u32(input)?;
+        let samples = read_u32(input)?;
+        let seed = u64::from_le_bytes(read_array(input)?);
+        let integrator = read_string(input)?.parse().map_err(invalid)?;
+        Ok(Self {
+            scene,
+            width,
+            height,
+            samples,
+            seed,
+            integrator,
+        })
+    }
+}
+
+/// How long either end waits on the other before giving up on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// For the coordinator to connect, and for a worker to then receive the
+    /// job.
+    pub connect: Duration,
+    /// For each reply, which for a tile only comes once it's rendered, so
+    /// this must be longer than any tile takes. A worker waits as long for
+    /// each tile, since the coordinator may be waiting on another worker's.
+    pub read: Duration,
+    pub write: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: Duration::from_secs(10),
+            read: Duration::from_secs(600),
+            write: Duration::from_secs(60),
+        }
+    }
+}
+
+/// A worker that was given up on. Its tile went back to the others.
+#[derive(Debug)]
+pub struct WorkerFailure {
+    pub address: String,
+    pub error: io::Error,
+}
+
+impl fmt::Display for WorkerFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let address = &self.address;
+        match self.error.kind() {
+            io::ErrorKind::UnexpectedEof => write!(f, "worker {address} hung up"),
+            // Depending on the platform, a read or write that times out
+            // fails with either.
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                write!(f, "worker {address} timed out")
+            }
+            _ => write!(f, "worker {address}: {}", self.error),
+        }
+    }
+}
+
+/// Renders `region` of the job's frame, in tiles of `tile_size` pixels
+/// spread over `workers`, given as addresses such as `localhost:7878`.
+/// Fails only if every worker does before all the tiles are done.
+/// `on_failure` hears about each worker that fails, as it happens.
+pub fn render_distributed(
+    job: &Job,
+    region: Region,
+    tile_size: u32,
+    workers: &[String],
+    timeouts: Timeouts,
+    on_failure: impl Fn(WorkerFailure) + Sync,
+) -> Result<Image> {
+    let mut tiles = region.tiles(tile_size);
+    // Tiles are taken from the end, and the top ones are wanted first.
+    tiles.reverse();
+    let queue = Queue {
+        state: Mutex::new(QueueState {
+            unfinished: tiles.len(),
+            pending: tiles,
+        }),
+        changed: Condvar::new(),
+    };
+    let image = Mutex::new(Image::new(region.width(), region.height()));
+
+    thread::scope(|scope| {
+        for address in workers {
+            let (queue, image, on_failure) = (&queue, &image, &on_failure);
+            scope.spawn(move || {
+                if let Err(error) = coordinate(address, job, region, timeouts, queue, image) {
+                    on_failure(WorkerFailure {
+                        address: address.clone(),
+                        error,
+                    });
+                }
+            });
+        }
+    });
+
+    let unfinished = queue.state.into_inner().unwrap().unfinished;
+    if unfinished > 0 {
+        return Err(Error::Render(format!(
+            "every worker failed, with {unfinished} tile(s) left"
+        )));
+    }
+    Ok(image.into_inner().unwrap())
+}
+
+/// Tiles waiting for a worker.
+struct Queue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+}
+
+struct QueueState {
+    pending: Vec<Region>,
+    /// Tiles not yet rendered, whether pending or being rendered.
+    unfinished: usize,
+}
+
+impl Queue {
+    /// The next tile to render, waiting if the only ones left are being
+    /// rendered elsewhere, in case they're given back. `None` once all are
+    /// done.
+    fn take(&self) -> Option<Region> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(tile) = state.pending.pop() {
+                return Some(tile);
+            }
+            if state.unfinished == 0 {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    fn finish(&self) {
+        self.state.lock().unwrap().unfinished -= 1;
+        self.changed.notify_all();
+    }
+
+    fn give_back(&self, tile: Region) {
+        self.state.lock().unwrap().pending.push(tile);
+        self.changed.notify_all();
+    }
+}
+
+/// Feeds one worker tiles until there are none left, or it fails.
+fn coordinate(
+    address: &str,
+    job: &Job,
+    region: Region,
+    timeouts: Timeouts,
+    queue: &Queue,
+    image: &Mutex<Image>,
+) -> io::Result<()> {
+    let stream = connect(address, timeouts.connect)?;
+    stream.set_read_timeout(Some(timeouts.read))?;
+    stream.set_write_timeout(Some(timeouts.write))?;
+    let mut input = BufReader::new(stream.try_clone()?);
+    let mut out = BufWriter::new(stream);
+    job.write(&mut out)?;
+    read_status(&mut input)?;
+
+    while let Some(tile) = queue.take() {
+        let pixels = match render_tile(&mut input, &mut out, tile) {
+            Ok(pixels) => pixels,
+            Err(e) => {
+                queue.give_back(tile);
+                return Err(e);
+            }
+        };
+        let mut image = image.lock().unwrap();
+        let mut pixels = pixels.into_iter();
+        for y in tile.y0..tile.y1 {
+            for x in tile.x0..tile.x1 {
+                let rgb = pixels.next().unwrap();
+                image.set(x - region.x0, y - region.y0, rgb);
+            }
+        }
+        drop(image);
+        queue.finish();
+    }
+    Ok(())
+}
+
+/// Tries each of the addresses `address` resolves to in turn.
+fn connect(address: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut error = None;
+    for address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => error = Some(e),
+        }
+    }
+    Err(error.unwrap_or_else(|| invalid("address resolves to nothing")))
+}
+
+fn render_tile(
+    input: &mut impl Read,
+    out: &mut impl Write,
+    tile: Region,
+) -> io::Result<Vec<[f32; 3]>> {
+    for n in [tile.x0, tile.y0, tile.x1, tile.y1] {
+        out.write_all(&n.to_le_bytes())?;
+    }
+    out.flush()?;
+    read_status(input)?;
+    let len = tile.width() as usize * tile.height() as usize;
+    let mut pixels = Vec::with_capacity(len);
+    for _ in 0..len {
+        let mut rgb = [0.; 3];
+        for c in &mut rgb {
+            *c = f32::from_le_bytes(read_array(input)?);
+        }
+        pixels.push(rgb);
+    }
+    Ok(pixels)
+}
+
+/// What [`serve`] is doing, for it to report.
+#[derive(Debug)]
+pub enum ServeEvent {
+    /// A coordinator connected.
+    Connected(SocketAddr),
+    /// A coordinator hung up, or the connection to it failed.
+    Finished(SocketAddr, io::Result<()>),
+}
+
+/// Renders tiles for coordinators, one at a time, forever. Problems with a
+/// coordinator are passed to `on_event`, and don't stop the worker. One that
+/// goes quiet for longer than the `timeouts` is hung up on, so that it can't
+/// keep the others waiting.
+pub fn serve(
+    listener: &TcpListener,
+    threads: usize,
+    timeouts: Timeouts,
+    mut on_event: impl FnMut(ServeEvent),
+) -> io::Result<()> {
+    loop {
+        let (stream, address) = listener.accept()?;
+        on_event(ServeEvent::Connected(address));
+        let result = serve_connection(stream, threads, timeouts);
+        on_event(ServeEvent::Finished(address, result));
+    }
+}
+
+/// Renders tiles for one coordinator until it hangs up.
+pub fn serve_connection(stream: TcpStream, threads: usize, timeouts: Timeouts) -> io::Result<()> {
+    stream.set_read_timeout(Some(timeouts.connect))?;
+    stream.set_write_timeout(Some(timeouts.write))?;
+    let mut input = BufReader::new(stream.try_clone()?);
+    let mut out = BufWriter::new(stream.try_clone()?);
+    let job = Job::read(&mut input)?;
+    stream.set_read_timeout(Some(timeouts.read))?;
+    let mut scene = match Scene::from_sdl(&job.scene) {
+        Ok(scene) => scene,
+        Err(e) => return send_failure(&mut out, &e.to_string()),
+    };
+    if job.width == 0 || job.height == 0 {
+        return send_failure(&mut out, "empty resolution");
+    }
+    scene.set_resolution(job.width, job.height);
+    out.write_all(&[OK])?;
+    out.flush()?;
+
+    let settings = RenderSettings {
+        samples: job.samples,
+        threads,
+        seed: job.seed,
+        integrator: job.integrator,
+        debug: None,
+    };
+    loop {
+        let mut x0 = [0; 4];
+        // The coordinator hangs up between tiles when it's done.
+        match input.read(&mut x0)? {
+            0 => return Ok(()),
+            n => input.read_exact(&mut x0[n..])?,
+        }
+        let tile = Region {
+            x0: u32::from_le_bytes(x0),
+            y0: read_u32(&mut input)?,
+            x1: read_u32(&mut input)?,
+            y1: read_u32(&mut input)?,
+        };
+        if let Err(message) = scene.set_region(tile) {
+            return send_failure(&mut out, &message);
+        }
+        let image = scene.render_with(&settings);
+        let mut bytes = Vec::with_capacity(1 + image.pixels().len() * 12);
+        bytes.push(OK);
+        for c in image.pixels().iter().flatten() {
+            bytes.extend_from_slice(&c.to_le_bytes());
+        }
+        out.write_all(&bytes)?;
+        out.flush()?;
+    }
+}
+
+/// Tells the coordinator why the worker is giving up.
+fn send_failure(out: &mut impl Write, message: &str) -> io::Result<()> {
+    out.write_all(&[FAILED])?;
+    write_string(out, message)?;
+    out.flush()?;
+    Err(io::Error::other(message.to_owned()))
+}
+
+fn read_status(input: &mut impl Read) -> io::Result<()> {
+    match read_array(input)? {
+        [OK] => Ok(()),
+        [FAILED] => Err(io::Error::other(read_string(input)?)),
+        _ => Err(invalid("unexpected reply")),
+    }
+}
+
+fn write_string(out: &mut impl Write, s: &str) -> io::Result<()> {
+    out.write_all(&(s.len() as u32).to_le_bytes())?;
+    out.write_all(s.as_bytes())
+}
+
+fn read_string(input: &mut impl Read) -> io::Result<String> {
+    let len = read_u32(input)?;
+    let mut s = String::new();
+    input.take(len as u64).read_to_string(&mut s)?;
+    if s.len() != len as usize {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(s)
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    read_array(input).map(u32::from_le_bytes)
+}
+
+fn read_array<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Starts a worker on a free port, returning its address.
+    fn worker() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || serve(&listener, 1, Timeouts::default(), |_| {}));
+        address
+    }
+
+    /// A worker that takes the job and its first tile, and then hangs up.
+    fn failing_worker() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut input = BufReader::new(stream.try_clone().unwrap());
+            let mut out = stream;
+            Job::read(&mut input).unwrap();
+            out.write_all(&[OK]).unwrap();
+            // There may be no tiles left by the time it's connected.
+            let _ = read_array::<16>(&mut input);
+        });
+        address
+    }
+
+    /// A worker that takes the job and its first tile, and then never
+    /// answers.
+    fn stalled_worker() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut input = BufReader::new(stream.try_clone().unwrap());
+            let mut out = stream;
+            Job::read(&mut input).unwrap();
+            out.write_all(&[OK]).unwrap();
+            // Until the coordinator gives up and hangs up.
+            while read_array::<16>(&mut input).is_ok() {}
+        });
+        address
+    }
+
+    fn job() -> (Job, Scene) {
+        let text = include_str!("../scene.sdl");
+        let mut scene = Scene::from_sdl(text).unwrap();
+        scene.set_resolution(24, 18);
+        let job = Job {
+            scene: text.to_owned(),
+            width: 24,
+            height: 18,
+            samples: 2,
+            seed: 3,
+            integrator: Integrator::Path,
+        };
+        (job, scene)
+    }
+
+    /// [`render_distributed`], also returning the workers' failures.
+    fn render(
+        job: &Job,
+        region: Region,
+        tile_size: u32,
+        workers: &[String],
+        timeouts: Timeouts,
+    ) -> (Result<Image>, Vec<String>) {
+        let failures = Mutex::new(Vec::new());
+        let image = render_distributed(job, region, tile_size, workers, timeouts, |failure| {
+            failures.lock().unwrap().push(failure.to_string())
+        });
+        (image, failures.into_inner().unwrap())
+    }
+
+    #[test]
+    fn same_as_one_process() {
+        let (job, mut scene) = job();
+        let settings = RenderSettings {
+            samples: job.samples,
+            seed: job.seed,
+            integrator: job.integrator,
+            ..RenderSettings::default()
+        };
+        let expected = scene.render_with(&settings);
+        let workers = [worker(), failing_worker(), worker(), failing_worker()];
+        let timeouts = Timeouts::default();
+        let (image, _) = render(&job, scene.screen(), 5, &workers, timeouts);
+        assert_eq!(image.unwrap(), expected);
+
+        let region = "3 4 20 9".parse().unwrap();
+        scene.set_region(region).unwrap();
+        let (image, failures) = render(&job, region, 8, &workers[..1], timeouts);
+        assert_eq!(image.unwrap(), scene.render_with(&settings));
+        assert!(failures.is_empty(), "{failures:?}");
+    }
+
+    #[test]
+    fn every_worker_failing() {
+        let (job, scene) = job();
+        // Nothing listens on a port that was just freed.
+        let unused = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().to_string()
+        };
+        let workers = [failing_worker(), unused];
+        let (image, failures) = render(&job, scene.screen(), 8, &workers, Timeouts::default());
+        assert_eq!(
+            image.unwrap_err().to_string(),
+            "render failed: every worker failed, with 9 tile(s) left"
+        );
+        assert_eq!(failures.len(), 2);
+        let hung_up = format!("worker {} hung up", workers[0]);
+        assert!(failures.contains(&hung_up), "{failures:?}");
+
+        // A scene the worker can't load fails it straight away.
+        let job = Job {
+            scene: "scene {".to_owned(),
+            ..job
+        };
+        let (image, failures) = render(&job, scene.screen(), 8, &[worker()], Timeouts::default());
+        let e = image.unwrap_err();
+        assert!(e.to_string().ends_with("with 9 tile(s) left"), "{e}");
+        assert!(
+            failures[0].contains("block is never closed"),
+            "{failures:?}"
+        );
+    }
+
+    #[test]
+    fn stalled_worker_times_out() {
+        let (job, scene) = job();
+        let expected = scene.render_with(&RenderSettings {
+            samples: job.samples,
+            seed: job.seed,
+            integrator: job.integrator,
+            ..RenderSettings::default()
+        });
+        let timeouts = Timeouts {
+            read: Duration::from_millis(200),
+            ..Timeouts::default()
+        };
+        let workers = [stalled_worker()];
+        let (image, failures) = render(&job, scene.screen(), 8, &workers, timeouts);
+        let e = image.unwrap_err();
+        assert!(e.to_string().ends_with("with 9 tile(s) left"), "{e}");
+        assert_eq!(failures, [format!("worker {} timed out", workers[0])]);
+
+        // Its tile goes to a worker that does answer.
+        let workers = [stalled_worker(), worker()];
+        let (image, _) = render(&job, scene.screen(), 8, &workers, timeouts);
+        assert_eq!(image.unwrap(), expected);
+    }
+
+    #[test]
+    fn idle_coordinator_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let timeouts = Timeouts {
+            connect: Duration::from_millis(200),
+            ..Timeouts::default()
+        };
+        let (events, received) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            serve(&listener, 1, timeouts, |event| {
+                if let ServeEvent::Finished(_, result) = event {
+                    events.send(result).unwrap();
+                }
+            })
+        });
+
+        // Connects, but never sends the job.
+        let idle = TcpStream::connect(&address).unwrap();
+        let e = received.recv().unwrap().unwrap_err();
+        assert!(
+            matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ),
+            "{e}"
+        );
+        drop(idle);
+
+        // The worker is free for the next coordinator.
+        let (job, scene) = job();
+        let (image, failures) = render(&job, scene.screen(), 8, &[address], timeouts);
+        assert!(failures.is_empty(), "{failures:?}");
+        image.unwrap();
+        assert!(received.recv().unwrap().is_ok());
+    }
+}
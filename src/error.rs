@@ -1,4 +1,190 @@
-use std::{error::Error as StdError, result::Result as StdResult};
+use std::{
+    error::Error as StdError,
+    fmt, io,
+    path::{Path, PathBuf},
+    result::Result as StdResult,
+};
+
+use crate::{
+    scene::BuildError,
+    sdl::{NodeError, ParseError, ValidationError},
+};
 
-pub type Error = Box<dyn StdError>;
 pub type Result<T> = StdResult<T, Error>;
+
+/// Everything that can go wrong between reading a scene and writing an image.
+#[derive(Debug)]
+pub enum Error {
+    /// A file couldn't be read or written.
+    Io {
+        path: PathBuf,
+        source: io::Error,
+        /// What was being done, e.g. `cannot read`. The path follows it in
+        /// the message.
+        context: String,
+    },
+    /// The scene file isn't well-formed SDL (or JSON).
+    Syntax(ParseError),
+    /// The scene file refers to something that isn't there: an undefined
+    /// material or definition, an include cycle, or includes and `use` lines
+    /// that add up to too many nodes.
+    Reference(NodeError),
+    /// The scene file is well-formed, but doesn't describe a scene: missing
+    /// or unknown keys, values of the wrong type...
+    Schema(ValidationError),
+    /// The scene describes geometry that can't be rendered, e.g. a sphere
+    /// with no radius.
+    Geometry(NodeError),
+    /// A scene built in code is incomplete, or can't be rendered.
+    Build(BuildError),
+    /// Producing the image failed.
+    Render(String),
+}
+
+impl Error {
+    /// An I/O error from reading `path`.
+    pub fn read(path: &Path, source: io::Error) -> Self {
+        Self::Io {
+            path: path.to_owned(),
+            context: "cannot read".to_owned(),
+            source,
+        }
+    }
+
+    /// An I/O error from writing `path`.
+    pub fn write(path: &Path, source: io::Error) -> Self {
+        Self::Io {
+            path: path.to_owned(),
+            context: "cannot write".to_owned(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io {
+                path,
+                source,
+                context,
+            } => write!(f, "{context} {:?}: {source}", path.display()),
+            Self::Syntax(e) => write!(f, "{e}"),
+            Self::Reference(e) => write!(f, "{e}"),
+            Self::Schema(e) => write!(f, "{e}"),
+            Self::Geometry(e) => write!(f, "{e}"),
+            Self::Build(e) => write!(f, "{e}"),
+            Self::Render(message) => write!(f, "render failed: {message}"),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::Syntax(e) => Some(e),
+            Self::Reference(e) => Some(e),
+            Self::Schema(e) => Some(e),
+            Self::Geometry(e) => Some(e),
+            Self::Build(e) => Some(e),
+            Self::Render(_) => None,
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Self {
+        Self::Syntax(e)
+    }
+}
+
+impl From<ValidationError> for Error {
+    fn from(e: ValidationError) -> Self {
+        Self::Schema(e)
+    }
+}
+
+impl From<BuildError> for Error {
+    fn from(e: BuildError) -> Self {
+        Self::Build(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::Scene;
+
+    const SCENE: &str = "scene {
+    camera {
+        position 0 0 -20
+    }
+    focal-distance 10
+    screen {
+        width 4
+        height 3
+    }
+    lights {
+        light {
+            position 5 0 -20
+        }
+    }
+    objects {
+        sphere {
+            center 0 0 0
+            radius 1
+        }
+    }
+}";
+
+    fn error(text: &str) -> Error {
+        Scene::from_sdl(text).err().expect("expected an error")
+    }
+
+    #[test]
+    fn variants() {
+        assert!(Scene::from_sdl(SCENE).is_ok());
+
+        let e = error(&SCENE.replace("radius 1", "radius 1 {"));
+        assert!(matches!(e, Error::Syntax(_)), "{e:?}");
+
+        let e = error(&SCENE.replace("radius 1", "radius one"));
+        let Error::Schema(ValidationError(errors)) = e else {
+            panic!("{e:?}");
+        };
+        assert_eq!(errors[0].path, "scene > objects > sphere > radius");
+
+        let e = error(&SCENE.replace("radius 1", "radius 0"));
+        let Error::Geometry(e) = e else {
+            panic!("{e:?}");
+        };
+        assert_eq!(e.path, "scene > objects > sphere");
+        assert_eq!(e.message, "radius must be positive, found 0");
+
+        let e = error(&SCENE.replace("width 4", "width 0"));
+        assert!(matches!(e, Error::Geometry(_)), "{e:?}");
+
+        let e = error(&SCENE.replace("radius 1", "material red"));
+        let Error::Reference(e) = e else {
+            panic!("{e:?}");
+        };
+        assert!(e.message.starts_with("undefined material"), "{e}");
+
+        let e = error(&SCENE.replace("radius 1", "include \"no/such/file.sdl\""));
+        let Error::Io {
+            path,
+            source,
+            context,
+        } = e
+        else {
+            panic!("{e:?}");
+        };
+        assert_eq!(path, Path::new("./no/such/file.sdl"));
+        assert_eq!(source.kind(), io::ErrorKind::NotFound);
+        assert_eq!(context, "cannot read the file included on line 18");
+
+        let e = error(&SCENE.replace("radius 1", "include 1"));
+        assert!(matches!(e, Error::Reference(_)), "{e:?}");
+    }
+}
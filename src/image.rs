@@ -0,0 +1,62 @@
+/// A rendered picture: RGB pixels, row by row from the top left, with each
+/// channel nominally in `0..=1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<[f32; 3]>,
+}
+
+impl Image {
+    /// An all-black image.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![[0.; 3]; width as usize * height as usize],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[[f32; 3]] {
+        &self.pixels
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        assert!(
+            x < self.width && y < self.height,
+            "({x}, {y}) out of bounds"
+        );
+        y as usize * self.width as usize + x as usize
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> [f32; 3] {
+        self.pixels[self.index(x, y)]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, rgb: [f32; 3]) {
+        let i = self.index(x, y);
+        self.pixels[i] = rgb;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    #[test]
+    fn get_and_set() {
+        let mut image = Image::new(2, 1);
+        image.set(1, 0, Color::new(255, 128, 3).to_rgb());
+        assert_eq!(image.get(1, 0), [1., 128. / 255., 3. / 255.]);
+        assert_eq!(image.pixels(), [[0.; 3], [1., 128. / 255., 3. / 255.]]);
+    }
+}
@@ -0,0 +1,29 @@
+//! A ray tracer that renders scenes described in SDL (or JSON).
+//!
+//! ```no_run
+//! use std::{io, path::Path};
+//!
+//! use ray_tracer::{output, Scene};
+//!
+//! let scene = Scene::from_sdl_file(Path::new("scene.sdl"))?;
+//! let image = scene.render();
+//! output::write_ppm(&image, &mut io::stdout())?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod color;
+pub mod distributed;
+pub mod error;
+pub mod image;
+pub mod objects;
+pub mod output;
+pub mod rng;
+pub mod scene;
+pub mod sdl;
+pub mod vec3;
+
+mod named;
+
+pub use error::{Error, Result};
+pub use image::Image;
+pub use scene::Scene;
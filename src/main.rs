@@ -1,22 +1,972 @@
-mod color;
-mod error;
-mod objects;
-mod scene;
-mod sdl;
-mod vec3;
-
-use std::{fs, env};
-
-use error::Result;
-use scene::Scene;
-
-fn main() -> Result<()> {
-    let mut args = env::args().skip(1);
-    if args.len() != 1 {
-        Err("expected 1 argument: <scene-file.sdl>")?
-    }
-    let filename = args.next().unwrap();
-    let text = fs::read_to_string(filename)?;
-    Scene::from_sdl(&text)?.render();
+use std::{
+    env, fmt, fs,
+    io::{self, BufWriter, Read, Write},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+    thread,
+    time::Duration,
+};
+
+use ray_tracer::{
+    distributed::{self, render_distributed, Job, ServeEvent, Timeouts},
+    output::{self, Format},
+    scene::{
+        scene_hash, Accumulator, Aov, Checkpoint, Passes, Region, RenderSettings, StopCondition,
+    },
+    sdl,
+    vec3::Vec3,
+    Error, Image, Scene,
+};
+
+type Result<T> = std::result::Result<T, CliError>;
+
+/// The library's errors, and the one only the command line can make.
+#[derive(Debug)]
+enum CliError {
+    /// The command line doesn't make sense.
+    Usage(String),
+    /// Loading, rendering or writing failed.
+    Run(Error),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Usage(message) => write!(f, "{message}"),
+            Self::Run(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl<E: Into<Error>> From<E> for CliError {
+    fn from(e: E) -> Self {
+        Self::Run(e.into())
+    }
+}
+
+fn main() -> ExitCode {
+    // Print errors with Display rather than Debug, so that parse errors
+    // show their source snippet.
+    match run() {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+const USAGE: &str = "usage:
+    ray-tracer render [options] <scene>    render a scene
+    ray-tracer render --worker <address> [-j <n>]
+                                           render tiles for a coordinator,
+                                           i.e. a render with --workers
+    ray-tracer <scene>                     the same as render, with default options
+    ray-tracer validate <scene>...         check that scenes load, without rendering
+    ray-tracer info <scene>                describe a scene
+    ray-tracer fmt [--check] <file>...     format SDL files in place
+    ray-tracer --help                      show this help
+
+A scene is an SDL or JSON file, or - for SDL on stdin.";
+
+const HELP: &str = "
+render options:
+    -o, --output <path>          where to write the image (default: stdout)
+    -f, --format <format>        ppm, ppm-ascii, pgm, pfm, png, png16, hdr, exr
+                                 or exr-uncompressed (default: from the output
+                                 file's extension, or ppm)
+    -r, --resolution <W>x<H>     override the scene's resolution, covering the
+                                 same width of the scene
+    --region <x0> <y0> <x1> <y1> render only the pixels from (x0, y0) up to but
+                                 not including (x1, y1), framed as in the whole
+                                 image
+    --tile <n>                   render only the nth tile, counting from 0 row by
+                                 row from the top left
+    --tile-size <n>              the width and height of tiles (default: 64)
+    --workers <address>,...      hand out tiles over TCP to workers, e.g.
+                                 localhost:7878, which were started with
+                                 `render --worker <address>`. Tiles from workers
+                                 that fail go to the others
+    --connect-timeout <seconds>  how long to wait to connect to a worker
+                                 (default: 10)
+    --worker-timeout <seconds>   how long to wait for a worker to take or render
+                                 a tile before giving it to another
+                                 (default: 600)
+    -s, --samples <n>            samples per pixel (default: 1)
+    -j, --threads <n>            render threads (default: one per CPU)
+    --seed <n>                   random seed for sampling (default: 0)
+    -i, --integrator <name>      direct or path (default: direct)
+    -d, --debug <shading>        shade for debugging instead: normals, depth,
+                                 uv (a checkerboard), albedo, shadow, or
+                                 intersections (tests per pixel)
+    -p, --progressive            render pass by pass, rewriting the output file
+                                 as it goes, until reaching --samples, --time or
+                                 --noise
+    --time <seconds>             stop a progressive render after this long
+    --noise <level>              stop a progressive render once the estimated
+                                 noise is this low, e.g. 0.01 (1% of white)
+    --refresh <seconds>          how often a progressive render rewrites the
+                                 output (default: 10)
+    --checkpoint <path>          save a progressive render there at each refresh,
+                                 so that it can be resumed
+    --resume <path>              continue a progressive render from a
+                                 checkpoint, with its seed and integrator, and
+                                 keep saving to it. Fails if the scene has
+                                 changed since
+    --aov <pass>,...             also write auxiliary passes: depth, normal,
+                                 albedo, object or position. EXR output holds
+                                 them all as extra channels; other formats
+                                 write each to its own file, e.g.
+                                 render.depth.png
+
+fmt options:
+    --check                      list files that aren't formatted, and fail if
+                                 there are any, instead of rewriting them";
+
+fn usage() -> CliError {
+    CliError::Usage(format!("{USAGE}\n\nrun `ray-tracer --help` for options"))
+}
+
+fn run() -> Result<ExitCode> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let Some((command, rest)) = args.split_first() else {
+        return Err(usage());
+    };
+    match command.as_str() {
+        "-h" | "--help" | "help" => {
+            println!("{USAGE}\n{HELP}");
+            Ok(ExitCode::SUCCESS)
+        }
+        "render" => match rest {
+            [flag, rest @ ..] if flag == "--worker" => worker(rest),
+            _ => render(&RenderArgs::parse(rest)?),
+        },
+        "validate" => validate(rest),
+        "info" => match rest {
+            [filename] => info(filename),
+            _ => Err(usage()),
+        },
+        "fmt" => format_files(rest),
+        _ if rest.is_empty() && !command.starts_with("--") => render(&RenderArgs::parse(&args)?),
+        _ => Err(usage()),
+    }
+}
+
+fn load_scene(filename: &str) -> Result<Scene> {
+    build_scene(load_tree(filename)?)
+}
+
+/// Builds the scene, printing any warnings.
+fn build_scene(tree: sdl::Node) -> Result<Scene> {
+    let (scene, warnings) = Scene::from_tree_with_warnings(tree)?;
+    for warning in warnings {
+        eprintln!("warning: {warning}");
+    }
+    Ok(scene)
+}
+
+/// The file is SDL unless its extension says otherwise. Includes are
+/// resolved.
+fn load_tree(filename: &str) -> Result<sdl::Node> {
+    let path = Path::new(filename);
+    if filename == "-" {
+        let mut tree: sdl::Node = read_input(filename)?.parse()?;
+        sdl::resolve_includes(&mut tree, Path::new("."))?;
+        Ok(tree)
+    } else if path.extension().is_some_and(|ext| ext == "json") {
+        Ok(sdl::parse_json_file(path)?)
+    } else {
+        Ok(sdl::parse_file(path)?)
+    }
+}
+
+fn read_input(filename: &str) -> Result<String> {
+    if filename == "-" {
+        let mut text = String::new();
+        io::stdin()
+            .read_to_string(&mut text)
+            .map_err(|e| Error::read(Path::new("<stdin>"), e))?;
+        Ok(text)
+    } else {
+        Ok(fs::read_to_string(filename).map_err(|e| Error::read(Path::new(filename), e))?)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct RenderArgs {
+    scene: String,
+    /// `None` for stdout.
+    output: Option<String>,
+    format: Format,
+    resolution: Option<(u32, u32)>,
+    crop: Option<Crop>,
+    /// For `--tile` and `--workers`.
+    tile_size: u32,
+    settings: RenderSettings,
+    aovs: Vec<Aov>,
+    progressive: Option<Progressive>,
+    /// Addresses of workers to render tiles on, if any.
+    workers: Vec<String>,
+    timeouts: Timeouts,
+}
+
+/// Which part of the screen to render.
+#[derive(Debug, PartialEq)]
+enum Crop {
+    Region(Region),
+    /// The nth of the screen's [`Region::tiles`].
+    Tile(usize),
+}
+
+#[derive(Debug, PartialEq)]
+struct Progressive {
+    stop: StopCondition,
+    /// How often the output is rewritten.
+    refresh: Duration,
+    /// Where to save the render as it goes, so that it can be resumed.
+    checkpoint: Option<PathBuf>,
+    /// A checkpoint to continue from.
+    resume: Option<PathBuf>,
+}
+
+impl RenderArgs {
+    fn parse(args: &[String]) -> Result<Self> {
+        let mut scene = None;
+        let mut output = None;
+        let mut format = None;
+        let mut resolution = None;
+        let mut region = None;
+        let mut tile = None;
+        let mut tile_size = None;
+        let mut workers = Vec::new();
+        let mut connect_timeout = None;
+        let mut worker_timeout = None;
+        let mut aovs = Vec::new();
+        let mut progressive = false;
+        let mut stop = StopCondition::default();
+        let mut refresh = None;
+        let mut checkpoint = None;
+        let mut resume = None;
+        let mut settings = RenderSettings {
+            threads: default_threads(),
+            ..RenderSettings::default()
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            // Options take their value from the next argument, or after an
+            // `=`.
+            let (option, inline) = match arg.split_once('=') {
+                Some((option, value)) if option.starts_with("--") => (option, Some(value)),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = || {
+                inline
+                    .or_else(|| args.next().map(String::as_str))
+                    .ok_or_else(|| CliError::Usage(format!("{option} needs a value")))
+            };
+            match option {
+                "-o" | "--output" => output = Some(value()?.to_owned()),
+                "-f" | "--format" => format = Some(parse_value(option, value()?)?),
+                "-r" | "--resolution" => {
+                    resolution = Some(parse_resolution(value()?).map_err(CliError::Usage)?)
+                }
+                "--region" => {
+                    // Four values, unless given as `--region=x0,y0,x1,y1`.
+                    let text = match inline {
+                        Some(text) => text.to_owned(),
+                        None => (0..4)
+                            .map(|_| value())
+                            .collect::<Result<Vec<_>>>()?
+                            .join(" "),
+                    };
+                    region = Some(parse_value(option, &text)?);
+                }
+                "--tile" => tile = Some(parse_value(option, value()?)?),
+                "--tile-size" => tile_size = Some(parse_positive(option, value()?)?),
+                "--workers" => workers.extend(value()?.split(',').map(str::to_owned)),
+                "--connect-timeout" => connect_timeout = Some(parse_seconds(option, value()?)?),
+                "--worker-timeout" => worker_timeout = Some(parse_seconds(option, value()?)?),
+                "-s" | "--samples" => {
+                    settings.samples = parse_positive(option, value()?)?;
+                    stop.samples = Some(settings.samples);
+                }
+                "-j" | "--threads" => settings.threads = parse_positive(option, value()?)?,
+                "--seed" => settings.seed = parse_value(option, value()?)?,
+                "-i" | "--integrator" => settings.integrator = parse_value(option, value()?)?,
+                "-d" | "--debug" => settings.debug = Some(parse_value(option, value()?)?),
+                "--aov" => {
+                    for name in value()?.split(',') {
+                        let aov = parse_value(option, name)?;
+                        if !aovs.contains(&aov) {
+                            aovs.push(aov);
+                        }
+                    }
+                }
+                "-p" | "--progressive" => progressive = true,
+                "--time" => stop.time = Some(parse_seconds(option, value()?)?),
+                "--noise" => stop.noise = Some(parse_positive_f64(option, value()?)?),
+                "--refresh" => refresh = Some(parse_seconds(option, value()?)?),
+                "--checkpoint" => checkpoint = Some(PathBuf::from(value()?)),
+                "--resume" => resume = Some(PathBuf::from(value()?)),
+                _ if option.starts_with('-') && option != "-" => {
+                    return Err(CliError::Usage(format!("unknown option {option}")));
+                }
+                _ if scene.is_some() => {
+                    return Err(CliError::Usage(format!("unexpected argument {arg:?}")));
+                }
+                _ => scene = Some(arg.clone()),
+            }
+        }
+
+        let scene = scene.ok_or_else(usage)?;
+        let crop = match (region, tile) {
+            (Some(_), Some(_)) => {
+                return Err(CliError::Usage(
+                    "--region and --tile can't be used together".to_owned(),
+                ))
+            }
+            (Some(region), None) => Some(Crop::Region(region)),
+            (None, Some(index)) => Some(Crop::Tile(index)),
+            (None, None) => None,
+        };
+        if tile_size.is_some() && tile.is_none() && workers.is_empty() {
+            return Err(CliError::Usage(
+                "--tile-size needs --tile or --workers".to_owned(),
+            ));
+        }
+        if workers.is_empty() && (connect_timeout.is_some() || worker_timeout.is_some()) {
+            return Err(CliError::Usage(
+                "--connect-timeout and --worker-timeout need --workers".to_owned(),
+            ));
+        }
+        let mut timeouts = Timeouts::default();
+        if let Some(timeout) = connect_timeout {
+            timeouts.connect = timeout;
+        }
+        if let Some(timeout) = worker_timeout {
+            (timeouts.read, timeouts.write) = (timeout, timeout);
+        }
+        if !workers.is_empty() && (progressive || settings.debug.is_some() || !aovs.is_empty()) {
+            return Err(CliError::Usage(
+                "--workers can't be used with --progressive, --debug or --aov".to_owned(),
+            ));
+        }
+        let progressive = if progressive {
+            if !stop.is_bounded() {
+                return Err(CliError::Usage(
+                    "--progressive needs --samples, --time or --noise to stop".to_owned(),
+                ));
+            }
+            if settings.debug.is_some() {
+                return Err(CliError::Usage(
+                    "--debug shading takes a single pass, so can't be progressive".to_owned(),
+                ));
+            }
+            Some(Progressive {
+                stop,
+                refresh: refresh.unwrap_or(Duration::from_secs(10)),
+                // Carry on saving to the checkpoint being resumed, unless
+                // told otherwise.
+                checkpoint: checkpoint.or_else(|| resume.clone()),
+                resume,
+            })
+        } else if stop.time.is_some()
+            || stop.noise.is_some()
+            || refresh.is_some()
+            || checkpoint.is_some()
+            || resume.is_some()
+        {
+            return Err(CliError::Usage(
+                "--time, --noise, --refresh, --checkpoint and --resume need --progressive"
+                    .to_owned(),
+            ));
+        } else {
+            None
+        };
+        let output = output.filter(|path| path != "-");
+        let format = format
+            .or_else(|| Format::from_extension(Path::new(output.as_deref()?)))
+            .unwrap_or_default();
+        Ok(Self {
+            scene,
+            output,
+            format,
+            resolution,
+            crop,
+            tile_size: tile_size.unwrap_or(64),
+            settings,
+            aovs,
+            progressive,
+            workers,
+            timeouts,
+        })
+    }
+}
+
+fn default_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+fn parse_value<T: FromStr>(option: &str, value: &str) -> Result<T>
+where
+    T::Err: fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|e| CliError::Usage(format!("{option} {value:?}: {e}")))
+}
+
+fn parse_positive<T: FromStr + Default + PartialEq>(option: &str, value: &str) -> Result<T>
+where
+    T::Err: fmt::Display,
+{
+    let n: T = parse_value(option, value)?;
+    if n == T::default() {
+        return Err(CliError::Usage(format!("{option} must be at least 1")));
+    }
+    Ok(n)
+}
+
+/// A positive number of seconds, which may be fractional.
+fn parse_positive_f64(option: &str, value: &str) -> Result<f64> {
+    let x: f64 = parse_value(option, value)?;
+    if !(x > 0. && x.is_finite()) {
+        return Err(CliError::Usage(format!("{option} must be positive")));
+    }
+    Ok(x)
+}
+
+fn parse_seconds(option: &str, value: &str) -> Result<Duration> {
+    Ok(Duration::from_secs_f64(parse_positive_f64(option, value)?))
+}
+
+/// `640x480`.
+fn parse_resolution(value: &str) -> std::result::Result<(u32, u32), String> {
+    let invalid = || format!("invalid resolution {value:?}, expected e.g. 640x480");
+    let (width, height) = value.split_once('x').ok_or_else(invalid)?;
+    let width: u32 = width.parse().map_err(|_| invalid())?;
+    let height: u32 = height.parse().map_err(|_| invalid())?;
+    if width == 0 || height == 0 {
+        return Err(invalid());
+    }
+    Ok((width, height))
+}
+
+fn render(args: &RenderArgs) -> Result<ExitCode> {
+    let tree = load_tree(&args.scene)?;
+    let text = tree.to_string();
+    let mut scene = build_scene(tree)?;
+    if let Some((width, height)) = args.resolution {
+        scene.set_resolution(width, height);
+    }
+    match args.crop {
+        Some(Crop::Region(region)) => scene.set_region(region).map_err(CliError::Usage)?,
+        Some(Crop::Tile(index)) => {
+            let size = args.tile_size;
+            let tiles = scene.screen().tiles(size);
+            let tile = tiles.get(index).ok_or_else(|| {
+                CliError::Usage(format!(
+                    "--tile {index}: the screen has only {} tile(s) of {size} pixels",
+                    tiles.len()
+                ))
+            })?;
+            scene.set_region(*tile).map_err(CliError::Usage)?;
+        }
+        None => {}
+    }
+    let output = args.output.as_deref().map(Path::new);
+    let passes = match &args.progressive {
+        Some(progressive) => Passes {
+            beauty: render_progressively(&scene, scene_hash(&text), args, progressive, output)?,
+            aovs: scene.render_aovs(args.settings.threads, &args.aovs),
+        },
+        None if !args.workers.is_empty() => {
+            let job = Job {
+                scene: text,
+                width: scene.screen().width(),
+                height: scene.screen().height(),
+                samples: args.settings.samples,
+                seed: args.settings.seed,
+                integrator: args.settings.integrator,
+            };
+            Passes {
+                beauty: render_distributed(
+                    &job,
+                    scene.region(),
+                    args.tile_size,
+                    &args.workers,
+                    args.timeouts,
+                    |failure| eprintln!("warning: {failure}"),
+                )?,
+                aovs: Vec::new(),
+            }
+        }
+        None if args.aovs.is_empty() => Passes {
+            beauty: scene.render_with(&args.settings),
+            aovs: Vec::new(),
+        },
+        None => scene.render_passes(&args.settings, &args.aovs),
+    };
+    if passes.aovs.is_empty() {
+        return write_output(output, |out| args.format.write(&passes.beauty, out));
+    }
+
+    if let Some(compression) = args.format.exr_compression() {
+        return write_output(output, |out| {
+            output::write_exr_passes(&passes, compression, out)
+        });
+    }
+    // Other formats hold one pass per file.
+    let Some(output) = output else {
+        return Err(CliError::Usage(format!(
+            "writing passes as {} needs --output",
+            args.format
+        )));
+    };
+    write_output(Some(output), |out| args.format.write(&passes.beauty, out))?;
+    for pass in &passes.aovs {
+        let path = pass_path(output, pass.aov());
+        write_output(Some(&path), |out| args.format.write(&pass.to_image(), out))?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Renders until the stop condition, reporting progress, and rewriting the
+/// output and checkpoint as it goes.
+fn render_progressively(
+    scene: &Scene,
+    scene_hash: u64,
+    args: &RenderArgs,
+    progressive: &Progressive,
+    output: Option<&Path>,
+) -> Result<Image> {
+    let mut settings = args.settings.clone();
+    let mut accumulator = match &progressive.resume {
+        Some(path) => {
+            let checkpoint = read_checkpoint(path, scene, scene_hash)?;
+            // The checkpoint's samples only make sense with more of the same.
+            settings.seed = checkpoint.seed;
+            settings.integrator = checkpoint.integrator;
+            eprintln!(
+                "resuming from {} sample(s) per pixel",
+                checkpoint.accumulator.passes()
+            );
+            checkpoint.accumulator
+        }
+        None => Accumulator::new(scene.width(), scene.height()),
+    };
+    scene.render_progressive(
+        &mut accumulator,
+        &settings,
+        &progressive.stop,
+        progressive.refresh,
+        |accumulator| {
+            eprintln!(
+                "{} sample(s) per pixel, noise {:.4}",
+                accumulator.passes(),
+                accumulator.noise()
+            );
+            if let Some(path) = output {
+                replace_file(path, |out| args.format.write(&accumulator.image(), out))?;
+            }
+            if let Some(path) = &progressive.checkpoint {
+                let checkpoint = Checkpoint {
+                    scene_hash,
+                    seed: settings.seed,
+                    integrator: settings.integrator,
+                    screen_width: scene.screen().width(),
+                    screen_height: scene.screen().height(),
+                    region: scene.region(),
+                    accumulator: accumulator.clone(),
+                };
+                replace_file(path, |out| checkpoint.write(out))?;
+            }
+            Ok::<_, CliError>(())
+        },
+    )?;
+    Ok(accumulator.image())
+}
+
+/// Refuses a checkpoint from a different scene, or of a different region or
+/// resolution.
+fn read_checkpoint(path: &Path, scene: &Scene, scene_hash: u64) -> Result<Checkpoint> {
+    let file = fs::File::open(path).map_err(|e| Error::read(path, e))?;
+    let checkpoint =
+        Checkpoint::read(&mut io::BufReader::new(file)).map_err(|e| Error::read(path, e))?;
+    if checkpoint.scene_hash != scene_hash {
+        return Err(CliError::Usage(format!(
+            "{:?} was rendered from a different version of the scene, so can't be resumed",
+            path.display()
+        )));
+    }
+    let screen = scene.screen();
+    if (checkpoint.screen_width, checkpoint.screen_height) != (screen.width(), screen.height()) {
+        return Err(CliError::Usage(format!(
+            "{:?} was rendered at {}x{}, but the render is {}x{}",
+            path.display(),
+            checkpoint.screen_width,
+            checkpoint.screen_height,
+            screen.width(),
+            screen.height()
+        )));
+    }
+    if checkpoint.region != scene.region() {
+        return Err(CliError::Usage(format!(
+            "{:?} covers pixels {}, but the render covers {}",
+            path.display(),
+            checkpoint.region,
+            scene.region()
+        )));
+    }
+    Ok(checkpoint)
+}
+
+/// `render --worker <address> [-j <n>]`: renders tiles for coordinators,
+/// until killed.
+fn worker(args: &[String]) -> Result<ExitCode> {
+    let (address, threads) = match args {
+        [address] => (address, default_threads()),
+        [address, option, n] if option == "-j" || option == "--threads" => {
+            (address, parse_positive(option, n)?)
+        }
+        _ => return Err(usage()),
+    };
+    let listener = TcpListener::bind(address.as_str())
+        .map_err(|e| CliError::Usage(format!("cannot listen on {address}: {e}")))?;
+    if let Ok(address) = listener.local_addr() {
+        eprintln!("listening on {address}");
+    }
+    distributed::serve(
+        &listener,
+        threads,
+        Timeouts::default(),
+        |event| match event {
+            ServeEvent::Connected(address) => eprintln!("rendering for {address}"),
+            ServeEvent::Finished(address, Ok(())) => eprintln!("done rendering for {address}"),
+            ServeEvent::Finished(address, Err(e)) => {
+                eprintln!("warning: coordinator {address}: {e}")
+            }
+        },
+    )
+    .map_err(|e| Error::Render(format!("worker stopped: {e}")))?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Writes a file next to `path`, then moves it into place, so that nothing
+/// watching `path` sees it half-written.
+fn replace_file(
+    path: &Path,
+    write: impl FnOnce(&mut Box<dyn Write>) -> io::Result<()>,
+) -> Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let partial = path.with_file_name(format!(".{name}.partial"));
+    write_output(Some(&partial), write)?;
+    fs::rename(&partial, path).map_err(|e| Error::write(path, e))?;
     Ok(())
 }
+
+/// Writes to `path`, or stdout if there's none.
+fn write_output(
+    path: Option<&Path>,
+    write: impl FnOnce(&mut Box<dyn Write>) -> io::Result<()>,
+) -> Result<ExitCode> {
+    let (mut out, name): (Box<dyn Write>, _) = match path {
+        Some(path) => {
+            let file = fs::File::create(path).map_err(|e| Error::write(path, e))?;
+            (Box::new(BufWriter::new(file)), path)
+        }
+        None => (
+            Box::new(BufWriter::new(io::stdout().lock())),
+            Path::new("<stdout>"),
+        ),
+    };
+    write(&mut out).map_err(|e| Error::write(name, e))?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// `render.png` becomes `render.depth.png`.
+fn pass_path(output: &Path, aov: Aov) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match output.extension() {
+        Some(ext) => format!("{stem}.{aov}.{}", ext.to_string_lossy()),
+        None => format!("{stem}.{aov}"),
+    };
+    output.with_file_name(name)
+}
+
+/// Loads each scene, reporting every failure rather than stopping at the
+/// first.
+fn validate(files: &[String]) -> Result<ExitCode> {
+    if files.is_empty() {
+        return Err(usage());
+    }
+    let mut failed = 0;
+    for filename in files {
+        match load_scene(filename) {
+            Ok(_) => println!("{filename}: ok"),
+            Err(e) => {
+                eprintln!("{filename}: {e}");
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        eprintln!("{failed} scene(s) failed to load");
+        return Ok(ExitCode::FAILURE);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn info(filename: &str) -> Result<ExitCode> {
+    let scene = load_scene(filename)?;
+    let camera = scene.camera();
+    let light = scene.light();
+    let [r, g, b] = light.color.channels();
+
+    println!("resolution      {}x{}", scene.width(), scene.height());
+    println!("camera          {}", coords(camera.position));
+    println!("focal distance  {}", scene.focal_distance());
+    println!(
+        "light           {}, color {r} {g} {b}, intensity {}",
+        coords(light.position),
+        light.intensity
+    );
+    println!("objects         {}", scene.objects().len());
+    Ok(ExitCode::SUCCESS)
+}
+
+fn coords(v: Vec3) -> String {
+    format!("{} {} {}", v.x(), v.y(), v.z())
+}
+
+/// Rewrites each file in canonical form. With `--check`, lists the files
+/// that would change instead, and fails if there are any.
+fn format_files(args: &[String]) -> Result<ExitCode> {
+    let check = args.first().is_some_and(|a| a == "--check");
+    let files = if check { &args[1..] } else { args };
+    if files.is_empty() {
+        return Err(usage());
+    }
+
+    let mut unformatted = 0;
+    for filename in files {
+        let text = read_input(filename)?;
+        let formatted = sdl::format(&text).map_err(|mut e| {
+            e.file = Some(filename.as_str().into());
+            e
+        })?;
+        if check {
+            if formatted != text {
+                println!("{filename}");
+                unformatted += 1;
+            }
+        } else if filename == "-" {
+            print!("{formatted}");
+        } else if formatted != text {
+            fs::write(filename, formatted).map_err(|e| Error::write(Path::new(filename), e))?;
+        }
+    }
+    if unformatted > 0 {
+        eprintln!("{unformatted} file(s) need formatting");
+        return Ok(ExitCode::FAILURE);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ray_tracer::scene::{DebugShading, Integrator};
+
+    fn parse(args: &str) -> Result<RenderArgs> {
+        let args: Vec<String> = args.split_whitespace().map(str::to_owned).collect();
+        RenderArgs::parse(&args)
+    }
+
+    #[test]
+    fn render_args() {
+        let args = parse(
+            "-o out.ppm --samples=8 -j 2 --seed 7 -i path -r 32x24 --aov depth,normal --aov depth \
+             -d normals scene.sdl",
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            RenderArgs {
+                scene: "scene.sdl".to_owned(),
+                output: Some("out.ppm".to_owned()),
+                format: Format::Ppm,
+                resolution: Some((32, 24)),
+                crop: None,
+                tile_size: 64,
+                settings: RenderSettings {
+                    samples: 8,
+                    threads: 2,
+                    seed: 7,
+                    integrator: Integrator::Path,
+                    debug: Some(DebugShading::Normals),
+                },
+                aovs: vec![Aov::Depth, Aov::Normal],
+                progressive: None,
+                workers: Vec::new(),
+                timeouts: Timeouts::default(),
+            }
+        );
+
+        let args = parse("- -o -").unwrap();
+        assert_eq!((args.scene.as_str(), args.output), ("-", None));
+
+        let args = parse("-p --time 1.5 --noise=0.01 scene.sdl").unwrap();
+        assert_eq!(
+            args.progressive,
+            Some(Progressive {
+                stop: StopCondition {
+                    samples: None,
+                    time: Some(Duration::from_millis(1500)),
+                    noise: Some(0.01),
+                },
+                refresh: Duration::from_secs(10),
+                checkpoint: None,
+                resume: None,
+            })
+        );
+        // Finer than a duration could hold.
+        let args = parse("-p --noise 1e-12 scene.sdl").unwrap();
+        assert_eq!(args.progressive.unwrap().stop.noise, Some(1e-12));
+        let args = parse("-p -s 64 --refresh 0.5 scene.sdl").unwrap();
+        let progressive = args.progressive.unwrap();
+        assert_eq!(progressive.stop.samples, Some(64));
+        assert_eq!(progressive.refresh, Duration::from_millis(500));
+
+        let resume = |args| {
+            let progressive = parse(args).unwrap().progressive.unwrap();
+            (progressive.checkpoint, progressive.resume)
+        };
+        let path = |path: &str| Some(PathBuf::from(path));
+        assert_eq!(
+            resume("-p -s 9 --resume a.ckpt scene.sdl"),
+            (path("a.ckpt"), path("a.ckpt"))
+        );
+        assert_eq!(
+            resume("-p -s 9 --resume a.ckpt --checkpoint b.ckpt scene.sdl"),
+            (path("b.ckpt"), path("a.ckpt"))
+        );
+
+        let crop = |args| parse(args).unwrap().crop;
+        let region = Region {
+            x0: 1,
+            y0: 2,
+            x1: 30,
+            y1: 40,
+        };
+        assert_eq!(
+            crop("--region 1 2 30 40 scene.sdl"),
+            Some(Crop::Region(region))
+        );
+        assert_eq!(
+            crop("--region=1,2,30,40 scene.sdl"),
+            Some(Crop::Region(region))
+        );
+        assert_eq!(crop("--tile 3 scene.sdl"), Some(Crop::Tile(3)));
+
+        let args = parse("--workers a:1,b:2 --workers c:3 --tile-size 16 scene.sdl").unwrap();
+        assert_eq!(args.workers, ["a:1", "b:2", "c:3"]);
+        assert_eq!((args.crop, args.tile_size), (None, 16));
+        assert_eq!(args.timeouts, Timeouts::default());
+
+        let args =
+            parse("--workers a:1 --connect-timeout 2 --worker-timeout=0.5 scene.sdl").unwrap();
+        assert_eq!(
+            args.timeouts,
+            Timeouts {
+                connect: Duration::from_secs(2),
+                read: Duration::from_millis(500),
+                write: Duration::from_millis(500),
+            }
+        );
+    }
+
+    #[test]
+    fn pass_paths() {
+        let path = pass_path(Path::new("out/render.png"), Aov::Depth);
+        assert_eq!(path, Path::new("out/render.depth.png"));
+        assert_eq!(
+            pass_path(Path::new("render"), Aov::Normal),
+            Path::new("render.normal")
+        );
+    }
+
+    #[test]
+    fn invalid_render_args() {
+        let message = |args| match parse(args) {
+            Err(CliError::Usage(message)) => message,
+            other => panic!("{other:?}"),
+        };
+        assert_eq!(
+            message("scene.sdl --samples 0"),
+            "--samples must be at least 1"
+        );
+        assert_eq!(message("scene.sdl --seed"), "--seed needs a value");
+        assert_eq!(
+            message("scene.sdl --seed x"),
+            "--seed \"x\": invalid digit found in string"
+        );
+        assert_eq!(message("scene.sdl --fast"), "unknown option --fast");
+        assert_eq!(message("a.sdl b.sdl"), "unexpected argument \"b.sdl\"");
+        assert_eq!(
+            message("scene.sdl -r 0x10"),
+            "invalid resolution \"0x10\", expected e.g. 640x480"
+        );
+        assert_eq!(
+            message("scene.sdl -f gif"),
+            "-f \"gif\": unknown format \"gif\" (expected one of: ppm, ppm-ascii, pgm, pfm, png, png16, hdr, exr, exr-uncompressed)"
+        );
+        assert!(message("-j 2").starts_with("usage:"));
+        assert_eq!(
+            message("-p scene.sdl"),
+            "--progressive needs --samples, --time or --noise to stop"
+        );
+        assert_eq!(
+            message("--time 5 scene.sdl"),
+            "--time, --noise, --refresh, --checkpoint and --resume need --progressive"
+        );
+        assert!(message("--resume a.ckpt scene.sdl").ends_with("need --progressive"));
+        assert_eq!(message("-p --time -1 scene.sdl"), "--time must be positive");
+        assert_eq!(
+            message("-p --noise 0 scene.sdl"),
+            "--noise must be positive"
+        );
+        assert_eq!(
+            message("-p --noise inf scene.sdl"),
+            "--noise must be positive"
+        );
+        assert_eq!(
+            message("--region 1 2 0 40 scene.sdl"),
+            "--region \"1 2 0 40\": expected x0 y0 x1 y1, with x0 < x1 and y0 < y1"
+        );
+        assert_eq!(message("--region 1 2 scene.sdl"), "--region needs a value");
+        assert_eq!(
+            message("--tile 1 --region 1 2 3 4 scene.sdl"),
+            "--region and --tile can't be used together"
+        );
+        assert_eq!(
+            message("--tile-size 8 scene.sdl"),
+            "--tile-size needs --tile or --workers"
+        );
+        assert_eq!(
+            message("--workers a:1 --aov depth scene.sdl"),
+            "--workers can't be used with --progressive, --debug or --aov"
+        );
+        assert_eq!(
+            message("--worker-timeout 5 scene.sdl"),
+            "--connect-timeout and --worker-timeout need --workers"
+        );
+        assert_eq!(
+            message("scene.sdl --aov depth,color"),
+            "--aov \"color\": unknown pass \"color\" (expected one of: depth, normal, albedo, object, position)"
+        );
+    }
+}
@@ -0,0 +1,58 @@
+//! Enums whose variants are picked by name, e.g. on the command line.
+
+/// Gives a fieldless enum an `ALL` array of its variants in order, a `name`
+/// for each, and [`FromStr`](std::str::FromStr) and
+/// [`Display`](std::fmt::Display) impls that use those names. `$what` says
+/// what the enum is in the error for an unknown name.
+///
+/// ```ignore
+/// named_variants! {
+///     Integrator, "integrator" {
+///         Direct => "direct",
+///         Path => "path",
+///     }
+/// }
+/// ```
+macro_rules! named_variants {
+    (
+        $ty:ident, $what:literal {
+            $($variant:ident => $name:literal),* $(,)?
+        }
+    ) => {
+        impl $ty {
+            pub const ALL: [Self; [$($name),*].len()] = [$(Self::$variant),*];
+
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Self::$variant => $name,)*
+                }
+            }
+        }
+
+        impl ::std::str::FromStr for $ty {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<Self, String> {
+                Self::ALL
+                    .into_iter()
+                    .find(|variant| variant.name() == s)
+                    .ok_or_else(|| {
+                        let names: Vec<_> = Self::ALL.map(Self::name).into();
+                        format!(
+                            "unknown {} {s:?} (expected one of: {})",
+                            $what,
+                            names.join(", ")
+                        )
+                    })
+            }
+        }
+
+        impl ::std::fmt::Display for $ty {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                f.write_str(self.name())
+            }
+        }
+    };
+}
+
+pub(crate) use named_variants;
@@ -8,10 +8,22 @@ use crate::{color::Color, scene::Ray, vec3::Vec3};
 pub use plane::Plane;
 pub use sphere::Sphere;
 
-pub trait Object: Any {
+pub trait Object: Any + Send + Sync {
     fn hit_test(&self, ray: Ray) -> Option<Vec3>;
 
     fn normal(&self, surface_point: Vec3) -> Vec3;
 
     fn color(&self) -> Color;
+
+    /// Coordinates on the surface at `point`, for texturing, in world units
+    /// of distance along the surface. `None` if the object doesn't have any.
+    fn uv(&self, _surface_point: Vec3) -> Option<(f64, f64)> {
+        None
+    }
+
+    /// Checks that the object's geometry can be rendered, e.g. that a
+    /// sphere's radius is positive. Returns the problem if not.
+    fn check(&self) -> Result<(), String> {
+        Ok(())
+    }
 }
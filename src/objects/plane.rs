@@ -21,6 +21,27 @@ impl Object for Plane {
     fn color(&self) -> Color {
         self.color
     }
+
+    /// Distances from the anchor along two directions in the plane.
+    fn uv(&self, point: Vec3) -> Option<(f64, f64)> {
+        let normal = self.normal.normalize();
+        let helper = if normal.x().abs() > 0.9 {
+            Vec3::Y_AXIS
+        } else {
+            Vec3::X_AXIS
+        };
+        let u = helper.cross_product(normal).normalize();
+        let v = normal.cross_product(u);
+        let d = point - self.anchor;
+        Some((d * u, d * v))
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if self.normal.norm_squared() == 0. {
+            return Err("normal must not be zero".to_owned());
+        }
+        Ok(())
+    }
 }
 
 fn plane_intersection(mut ray: Ray, plane: Plane) -> Option<Vec3> {
@@ -72,4 +93,17 @@ mod tests {
         assert!(hit.is_some());
         dbg!(hit);
     }
+
+    #[test]
+    fn uv() {
+        let obj = Plane {
+            anchor: Vec3::new(1., 2., 3.),
+            normal: Vec3::new(0., 0., -2.),
+            color: Color::WHITE,
+        };
+        assert_eq!(obj.uv(obj.anchor), Some((0., 0.)));
+        let (u, v) = obj.uv(Vec3::new(4., 6., 3.)).unwrap();
+        // Distances are preserved.
+        assert!((u.hypot(v) - 5.).abs() < 1e-12, "{u} {v}");
+    }
 }
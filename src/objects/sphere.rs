@@ -21,6 +21,22 @@ impl Object for Sphere {
     fn color(&self) -> Color {
         self.color
     }
+
+    /// Longitude and colatitude, scaled by the radius, so that they're
+    /// distances at the equator.
+    fn uv(&self, point: Vec3) -> Option<(f64, f64)> {
+        let d = (point - self.center).normalize();
+        let longitude = d.z().atan2(d.x());
+        let colatitude = d.y().clamp(-1., 1.).acos();
+        Some((longitude * self.radius, colatitude * self.radius))
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if self.radius <= 0. {
+            return Err(format!("radius must be positive, found {}", self.radius));
+        }
+        Ok(())
+    }
 }
 
 fn sphere_intersection(ray: Ray, sphere: Sphere) -> Option<Vec3> {
@@ -52,7 +68,7 @@ fn sphere_intersection_origin(c: Vec3, d: Vec3, r: f64) -> Option<Vec3> {
         let b = 2. * c.dot_product(d);
         let c = c.norm_squared() - r.powf(2.);
         let solutions = solve_quadratic(a, b, c)?;
-        solutions.into_iter().filter(|&t| t > 0.).next()?
+        solutions.into_iter().find(|&t| t > 0.)?
     };
     Some(c + t * d)
 }
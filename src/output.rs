@@ -0,0 +1,116 @@
+//! Writing rendered images to files.
+
+mod exr;
+mod hdr;
+mod netpbm;
+mod png;
+mod zlib;
+
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{image::Image, named::named_variants};
+
+pub use exr::{write_exr, write_exr_channels, write_exr_passes, ExrCompression};
+pub use hdr::write_hdr;
+pub use netpbm::{write_pfm, write_pgm, write_ppm, write_ppm_ascii};
+pub use png::{crc32, write_png, write_png_rgba, BitDepth};
+pub use zlib::{adler32, compress};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// Binary PPM.
+    #[default]
+    Ppm,
+    /// Plain-text PPM.
+    PpmAscii,
+    /// Binary grayscale PGM.
+    Pgm,
+    /// Portable float map, for HDR data.
+    Pfm,
+    /// 8-bit RGB PNG.
+    Png,
+    /// 16-bit RGB PNG.
+    Png16,
+    /// Radiance RGBE.
+    Hdr,
+    /// OpenEXR, with ZIP compression.
+    Exr,
+    /// OpenEXR, uncompressed.
+    ExrUncompressed,
+}
+
+named_variants! {
+    Format, "format" {
+        Ppm => "ppm",
+        PpmAscii => "ppm-ascii",
+        Pgm => "pgm",
+        Pfm => "pfm",
+        Png => "png",
+        Png16 => "png16",
+        Hdr => "hdr",
+        Exr => "exr",
+        ExrUncompressed => "exr-uncompressed",
+    }
+}
+
+impl Format {
+    /// The format for a file name, if its extension is a known one.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "ppm" => Some(Self::Ppm),
+            "pgm" => Some(Self::Pgm),
+            "pfm" => Some(Self::Pfm),
+            "png" => Some(Self::Png),
+            "hdr" => Some(Self::Hdr),
+            "exr" => Some(Self::Exr),
+            _ => None,
+        }
+    }
+
+    /// For OpenEXR formats, which can hold several passes.
+    pub fn exr_compression(self) -> Option<ExrCompression> {
+        match self {
+            Self::Exr => Some(ExrCompression::Zip),
+            Self::ExrUncompressed => Some(ExrCompression::None),
+            _ => None,
+        }
+    }
+
+    pub fn write(self, image: &Image, out: &mut impl Write) -> io::Result<()> {
+        match self {
+            Self::Ppm => write_ppm(image, out),
+            Self::PpmAscii => write_ppm_ascii(image, out),
+            Self::Pgm => write_pgm(image, out),
+            Self::Pfm => write_pfm(image, out),
+            Self::Png => write_png(image, BitDepth::Eight, out),
+            Self::Png16 => write_png(image, BitDepth::Sixteen, out),
+            Self::Hdr => write_hdr(image, out),
+            Self::Exr | Self::ExrUncompressed => {
+                let compression = self.exr_compression().expect("an EXR format");
+                write_exr(image, compression, out)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_and_extensions() {
+        for format in Format::ALL {
+            assert_eq!(format.name().parse(), Ok(format));
+        }
+        assert_eq!(
+            Format::from_extension(Path::new("out/image.PFM")),
+            Some(Format::Pfm)
+        );
+        assert_eq!(Format::from_extension(Path::new("image.gif")), None);
+        assert_eq!(Format::from_extension(Path::new("image")), None);
+    }
+}
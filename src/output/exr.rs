@@ -0,0 +1,344 @@
+use std::io::{self, Write};
+
+use crate::{image::Image, scene::Passes};
+
+use super::zlib;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExrCompression {
+    None,
+    /// zlib, in blocks of 16 scanlines.
+    #[default]
+    Zip,
+}
+
+impl ExrCompression {
+    /// The value of the `compression` attribute.
+    fn id(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Zip => 3,
+        }
+    }
+
+    fn lines_per_block(self) -> u32 {
+        match self {
+            Self::None => 1,
+            Self::Zip => 16,
+        }
+    }
+}
+
+/// Writes the image as the `R`, `G` and `B` channels of an OpenEXR file.
+pub fn write_exr(
+    image: &Image,
+    compression: ExrCompression,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let [r, g, b] = [0, 1, 2].map(|i| image.pixels().iter().map(|p| p[i]).collect::<Vec<_>>());
+    let channels = [("R", &r[..]), ("G", &g[..]), ("B", &b[..])];
+    write_exr_channels(image.width(), image.height(), &channels, compression, out)
+}
+
+/// Writes the beauty image as `R`, `G` and `B`, and each AOV as its
+/// [channels](crate::scene::Aov::channels).
+pub fn write_exr_passes(
+    passes: &Passes,
+    compression: ExrCompression,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let beauty = &passes.beauty;
+    let mut values: Vec<(&str, Vec<f32>)> = ["R", "G", "B"]
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| (name, beauty.pixels().iter().map(|p| p[i]).collect()))
+        .collect();
+    for pass in &passes.aovs {
+        for (i, &name) in pass.aov().channels().iter().enumerate() {
+            values.push((name, pass.channel(i)));
+        }
+    }
+    let channels: Vec<(&str, &[f32])> = values.iter().map(|(n, v)| (*n, &v[..])).collect();
+    write_exr_channels(beauty.width(), beauty.height(), &channels, compression, out)
+}
+
+/// Writes a single-part scanline OpenEXR file with any number of named
+/// 32-bit float channels, e.g. `R`, `G`, `B` and `Z`, or `normal.X`. Each
+/// channel has a value per pixel, row by row from the top.
+pub fn write_exr_channels(
+    width: u32,
+    height: u32,
+    channels: &[(&str, &[f32])],
+    compression: ExrCompression,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let pixels = width as usize * height as usize;
+    for (name, values) in channels {
+        assert_eq!(values.len(), pixels, "channel {name:?} has the wrong size");
+    }
+    // Readers expect channels in alphabetical order.
+    let mut channels = channels.to_vec();
+    channels.sort_by_key(|&(name, _)| name);
+    if let Some(pair) = channels.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+        let message = format!("duplicate channel {:?}", pair[0].0);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    }
+
+    let mut header = Vec::new();
+    // Magic number, then version 2 with no flags: a single scanline part.
+    header.extend([0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+    let mut list = Vec::new();
+    for (name, _) in &channels {
+        list.extend(name.as_bytes());
+        list.push(0);
+        // Float pixels, not perceptually linear, reserved, then x and y
+        // sampling.
+        list.extend(2i32.to_le_bytes());
+        list.extend([0, 0, 0, 0]);
+        list.extend(1i32.to_le_bytes());
+        list.extend(1i32.to_le_bytes());
+    }
+    list.push(0);
+    attribute(&mut header, "channels", "chlist", &list);
+    attribute(
+        &mut header,
+        "compression",
+        "compression",
+        &[compression.id()],
+    );
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    // Increasing y.
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    let lines = compression.lines_per_block();
+    let mut blocks = Vec::new();
+    for first in (0..height).step_by(lines as usize) {
+        let mut raw = Vec::new();
+        for y in first..(first + lines).min(height) {
+            let row = y as usize * width as usize..(y as usize + 1) * width as usize;
+            for (_, values) in &channels {
+                raw.extend(values[row.clone()].iter().flat_map(|v| v.to_le_bytes()));
+            }
+        }
+        let data = match compression {
+            ExrCompression::None => raw,
+            ExrCompression::Zip => {
+                let compressed = zlib::compress(&predict(&interleave(&raw)));
+                // Data no smaller than the raw size is read as uncompressed.
+                if compressed.len() < raw.len() {
+                    compressed
+                } else {
+                    raw
+                }
+            }
+        };
+        let mut block = Vec::with_capacity(data.len() + 8);
+        block.extend((first as i32).to_le_bytes());
+        block.extend((data.len() as u32).to_le_bytes());
+        block.extend(data);
+        blocks.push(block);
+    }
+
+    // The offset table gives each block's position in the file.
+    let mut offset = (header.len() + 8 * blocks.len()) as u64;
+    for block in &blocks {
+        header.extend(offset.to_le_bytes());
+        offset += block.len() as u64;
+    }
+    out.write_all(&header)?;
+    for block in blocks {
+        out.write_all(&block)?;
+    }
+    out.flush()
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    for s in [name, kind] {
+        header.extend(s.as_bytes());
+        header.push(0);
+    }
+    header.extend((value.len() as u32).to_le_bytes());
+    header.extend(value);
+}
+
+/// Puts the even-indexed bytes before the odd ones, so that the similar
+/// high bytes of neighbouring values end up together.
+fn interleave(raw: &[u8]) -> Vec<u8> {
+    let even = raw.iter().step_by(2);
+    let odd = raw.iter().skip(1).step_by(2);
+    even.chain(odd).copied().collect()
+}
+
+/// Replaces each byte with its difference from the one before, plus 128.
+fn predict(bytes: &[u8]) -> Vec<u8> {
+    let mut out = bytes.to_vec();
+    for i in 1..bytes.len() {
+        out[i] = bytes[i].wrapping_sub(bytes[i - 1]).wrapping_add(128);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::zlib::tests::inflate;
+
+    /// The channel names and values, and the compression.
+    fn decode(exr: &[u8]) -> (Vec<(String, Vec<f32>)>, u8) {
+        assert_eq!(exr[..8], [0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+        let mut pos = 8;
+        let string = |pos: &mut usize| {
+            let len = exr[*pos..].iter().position(|&b| b == 0).unwrap();
+            let s = String::from_utf8(exr[*pos..*pos + len].to_vec()).unwrap();
+            *pos += len + 1;
+            s
+        };
+        let i32_at = |pos: usize| i32::from_le_bytes(exr[pos..pos + 4].try_into().unwrap());
+
+        let mut names = Vec::new();
+        let mut compression = None;
+        let mut window = None;
+        loop {
+            let name = string(&mut pos);
+            if name.is_empty() {
+                break;
+            }
+            let _kind = string(&mut pos);
+            let size = i32_at(pos) as usize;
+            pos += 4;
+            let value = pos;
+            match name.as_str() {
+                "channels" => {
+                    let mut p = value;
+                    loop {
+                        let channel = string(&mut p);
+                        if channel.is_empty() {
+                            break;
+                        }
+                        assert_eq!(i32_at(p), 2, "not float");
+                        p += 16;
+                        names.push(channel);
+                    }
+                    assert_eq!(p, value + size);
+                }
+                "compression" => compression = Some(exr[value]),
+                "dataWindow" => window = Some([0, 4, 8, 12].map(|i| i32_at(value + i))),
+                _ => {}
+            }
+            pos += size;
+        }
+        let [0, 0, x_max, y_max] = window.unwrap() else {
+            panic!("unexpected window");
+        };
+        let (width, height) = (x_max as usize + 1, y_max as usize + 1);
+        let compression = compression.unwrap();
+        let lines = if compression == 3 { 16 } else { 1 };
+
+        let mut channels: Vec<(String, Vec<f32>)> =
+            names.into_iter().map(|name| (name, Vec::new())).collect();
+        let blocks = height.div_ceil(lines);
+        for i in 0..blocks {
+            let offset = u64::from_le_bytes(exr[pos + 8 * i..][..8].try_into().unwrap()) as usize;
+            assert_eq!(i32_at(offset) as usize, i * lines);
+            let size = i32_at(offset + 4) as usize;
+            let data = &exr[offset + 8..offset + 8 + size];
+            let lines = lines.min(height - i * lines);
+            let raw_size = lines * width * channels.len() * 4;
+            let raw = if size < raw_size {
+                let mut bytes = inflate(data);
+                for i in 1..bytes.len() {
+                    bytes[i] = bytes[i - 1].wrapping_add(bytes[i]).wrapping_sub(128);
+                }
+                let (even, odd) = bytes.split_at(bytes.len().div_ceil(2));
+                let mut raw = Vec::new();
+                for i in 0..bytes.len() {
+                    raw.push(if i % 2 == 0 { even[i / 2] } else { odd[i / 2] });
+                }
+                raw
+            } else {
+                data.to_vec()
+            };
+            assert_eq!(raw.len(), raw_size);
+
+            let mut values = raw
+                .chunks(4)
+                .map(|b| f32::from_le_bytes(b.try_into().unwrap()));
+            for _ in 0..lines {
+                for (_, channel) in &mut channels {
+                    channel.extend(values.by_ref().take(width));
+                }
+            }
+        }
+        (channels, compression)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (width, height) = (7, 19);
+        let ramp: Vec<f32> = (0..width * height).map(|i| i as f32 * 0.25).collect();
+        let hdr: Vec<f32> = (0..width * height).map(|i| (i as f32).exp2()).collect();
+        let depth = vec![f32::INFINITY; width * height];
+        let channels = [("Z", &depth[..]), ("R", &ramp), ("G", &hdr), ("B", &ramp)];
+
+        for (compression, id) in [(ExrCompression::None, 0), (ExrCompression::Zip, 3)] {
+            let mut out = Vec::new();
+            write_exr_channels(
+                width as u32,
+                height as u32,
+                &channels,
+                compression,
+                &mut out,
+            )
+            .unwrap();
+            let (decoded, decoded_id) = decode(&out);
+            assert_eq!(decoded_id, id);
+
+            let names: Vec<&str> = decoded.iter().map(|(name, _)| name.as_str()).collect();
+            assert_eq!(names, ["B", "G", "R", "Z"]);
+            for (name, values) in decoded {
+                let (_, expected) = channels.iter().find(|(n, _)| *n == name).unwrap();
+                assert_eq!(values, *expected, "{name}");
+            }
+        }
+    }
+
+    #[test]
+    fn image_channels() {
+        let mut image = Image::new(3, 2);
+        image.set(2, 1, [1.5, -2., 1e6]);
+        let mut out = Vec::new();
+        write_exr(&image, ExrCompression::Zip, &mut out).unwrap();
+        let (channels, _) = decode(&out);
+        assert_eq!(channels[0], ("B".to_owned(), vec![0., 0., 0., 0., 0., 1e6]));
+        assert_eq!(channels[2].1[5], 1.5);
+    }
+
+    #[test]
+    fn duplicate_channels() {
+        let values = [0.; 4];
+        let channels = [("R", &values[..]), ("R", &values[..])];
+        let e =
+            write_exr_channels(2, 2, &channels, ExrCompression::None, &mut Vec::new()).unwrap_err();
+        assert_eq!(e.to_string(), "duplicate channel \"R\"");
+    }
+}
@@ -0,0 +1,165 @@
+use std::io::{self, Write};
+
+use crate::image::Image;
+
+/// Writes a Radiance RGBE (`.hdr`) file: linear, unclamped, with an 8-bit
+/// mantissa per channel and a shared exponent. Negative values become zero.
+pub fn write_hdr(image: &Image, out: &mut impl Write) -> io::Result<()> {
+    let (width, height) = (image.width(), image.height());
+    write!(
+        out,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {height} +X {width}\n"
+    )?;
+    let mut bytes = Vec::new();
+    for row in image.pixels().chunks(width.max(1) as usize) {
+        let pixels: Vec<[u8; 4]> = row.iter().map(|&rgb| rgbe(rgb)).collect();
+        // Run-length encoding only works for rows of this size.
+        if (8..0x8000).contains(&width) {
+            bytes.extend([2, 2, (width >> 8) as u8, width as u8]);
+            for channel in 0..4 {
+                let values: Vec<u8> = pixels.iter().map(|p| p[channel]).collect();
+                run_length_encode(&values, &mut bytes);
+            }
+        } else {
+            bytes.extend(pixels.iter().flatten());
+        }
+    }
+    out.write_all(&bytes)?;
+    out.flush()
+}
+
+fn rgbe(rgb: [f32; 3]) -> [u8; 4] {
+    let rgb = rgb.map(|c| if c > 0. { c } else { 0. });
+    let max = rgb[0].max(rgb[1]).max(rgb[2]);
+    if max < 1e-32 {
+        return [0; 4];
+    }
+    // max = mantissa * 2^exponent, with the mantissa in 0.5..1.
+    let exponent = max.log2().floor() as i32 + 1;
+    let scale = 256. / 2f32.powi(exponent);
+    let [r, g, b] = rgb.map(|c| (c * scale).min(255.) as u8);
+    [r, g, b, (exponent + 128).clamp(0, 255) as u8]
+}
+
+/// Encodes one channel of a row as runs (a count above 128, then the value
+/// to repeat) and literals (a count, then that many values).
+fn run_length_encode(values: &[u8], out: &mut Vec<u8>) {
+    const MIN_RUN: usize = 4;
+    let mut i = 0;
+    while i < values.len() {
+        let run = values[i..]
+            .iter()
+            .take(127)
+            .take_while(|&&v| v == values[i])
+            .count();
+        if run >= MIN_RUN {
+            out.extend([128 + run as u8, values[i]]);
+            i += run;
+            continue;
+        }
+        // Literals up to the next run worth encoding.
+        let start = i;
+        while i < values.len() && i - start < 128 {
+            let run = values[i..]
+                .iter()
+                .take(MIN_RUN)
+                .take_while(|&&v| v == values[i])
+                .count();
+            if run >= MIN_RUN {
+                break;
+            }
+            i += 1;
+        }
+        out.push((i - start) as u8);
+        out.extend(&values[start..i]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(hdr: &[u8]) -> Image {
+        let text = String::from_utf8_lossy(hdr);
+        let header_end = text.find("\n\n").unwrap() + 2;
+        assert!(text.starts_with("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n"));
+        let size_end = header_end + text[header_end..].find('\n').unwrap();
+        let size: Vec<&str> = text[header_end..size_end].split(' ').collect();
+        let [_, height, _, width] = size[..] else {
+            panic!("{size:?}");
+        };
+        let (width, height): (u32, u32) = (width.parse().unwrap(), height.parse().unwrap());
+
+        let mut image = Image::new(width, height);
+        let mut data = hdr[size_end + 1..].iter().copied();
+        for y in 0..height {
+            let mut pixels = vec![[0u8; 4]; width as usize];
+            if (8..0x8000).contains(&width) {
+                let marker: Vec<u8> = data.by_ref().take(4).collect();
+                assert_eq!(marker, [2, 2, (width >> 8) as u8, width as u8]);
+                let channels: Vec<Vec<u8>> = (0..4)
+                    .map(|_| {
+                        let mut values = Vec::new();
+                        while values.len() < width as usize {
+                            let count = data.next().unwrap() as usize;
+                            if count > 128 {
+                                let value = data.next().unwrap();
+                                values.extend([value].repeat(count - 128));
+                            } else {
+                                values.extend(data.by_ref().take(count));
+                            }
+                        }
+                        assert_eq!(values.len(), width as usize);
+                        values
+                    })
+                    .collect();
+                for (x, pixel) in pixels.iter_mut().enumerate() {
+                    *pixel = [0, 1, 2, 3].map(|c| channels[c][x]);
+                }
+            } else {
+                for pixel in &mut pixels {
+                    for c in pixel {
+                        *c = data.next().unwrap();
+                    }
+                }
+            }
+            for (x, [r, g, b, e]) in (0..).zip(pixels) {
+                let scale = if e == 0 {
+                    0.
+                } else {
+                    2f32.powi(e as i32 - 136)
+                };
+                image.set(x, y, [r, g, b].map(|c| (c as f32 + 0.5) * scale));
+            }
+        }
+        assert_eq!(data.next(), None);
+        image
+    }
+
+    #[test]
+    fn round_trip() {
+        for width in [5, 40] {
+            let mut image = Image::new(width, 3);
+            for x in 0..width {
+                // Runs, and values above 1.
+                image.set(x, 1, [0.5, (x / 6) as f32 * 10., 1e-3]);
+                image.set(x, 2, [-1., 300., x as f32]);
+            }
+            let mut out = Vec::new();
+            write_hdr(&image, &mut out).unwrap();
+            let decoded = decode(&out);
+
+            assert_eq!(decoded.width(), width);
+            for (&expected, actual) in image.pixels().iter().zip(decoded.pixels()) {
+                let max = expected.into_iter().fold(0f32, f32::max);
+                for (e, a) in expected.into_iter().zip(actual) {
+                    // Precision is relative to the brightest channel.
+                    assert!(
+                        (e.max(0.) - a).abs() <= max / 128.,
+                        "{expected:?} {actual:?}"
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::io::{self, Write};
+
+use crate::{color::Color, image::Image};
+
+/// Writes a binary (P6) PPM file, 8 bits per channel.
+pub fn write_ppm(image: &Image, out: &mut impl Write) -> io::Result<()> {
+    write!(out, "P6\n{} {}\n255\n", image.width(), image.height())?;
+    let bytes: Vec<u8> = image
+        .pixels()
+        .iter()
+        .flat_map(|&rgb| Color::from_rgb(rgb).channels())
+        .collect();
+    out.write_all(&bytes)?;
+    out.flush()
+}
+
+/// Writes a plain-text (P3) PPM file: larger and slower than [`write_ppm`],
+/// but readable.
+pub fn write_ppm_ascii(image: &Image, out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "P3")?;
+    writeln!(out, "{} {} 255", image.width(), image.height())?;
+    writeln!(out)?;
+
+    for &rgb in image.pixels() {
+        let [r, g, b] = Color::from_rgb(rgb).channels();
+        writeln!(out, "{r} {g} {b}")?;
+    }
+    out.flush()
+}
+
+/// Writes a binary (P5) PGM file of the image's luminance, 8 bits per pixel.
+/// Single-channel passes, with equal channels, come out unchanged.
+pub fn write_pgm(image: &Image, out: &mut impl Write) -> io::Result<()> {
+    write!(out, "P5\n{} {}\n255\n", image.width(), image.height())?;
+    let bytes: Vec<u8> = image
+        .pixels()
+        .iter()
+        .map(|&rgb| (luminance(rgb).clamp(0., 1.) * 255.).round() as u8)
+        .collect();
+    out.write_all(&bytes)?;
+    out.flush()
+}
+
+/// Writes a color portable float map: the channels as they are, without
+/// clamping, so that HDR data survives.
+pub fn write_pfm(image: &Image, out: &mut impl Write) -> io::Result<()> {
+    // A negative scale means little-endian.
+    write!(out, "PF\n{} {}\n-1.0\n", image.width(), image.height())?;
+    let width = image.width().max(1) as usize;
+    let mut bytes = Vec::with_capacity(image.pixels().len() * 12);
+    // Rows go from the bottom up.
+    for row in image.pixels().chunks(width).rev() {
+        for c in row.iter().flatten() {
+            bytes.extend_from_slice(&c.to_le_bytes());
+        }
+    }
+    out.write_all(&bytes)?;
+    out.flush()
+}
+
+/// Rec. 709 luma weights, which sum to 1.
+fn luminance([r, g, b]: [f32; 3]) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> Image {
+        let mut image = Image::new(2, 2);
+        image.set(1, 0, Color::new(255, 128, 3).to_rgb());
+        image.set(0, 1, [0.25; 3]);
+        image.set(1, 1, [2., 0., -1.]);
+        image
+    }
+
+    fn written(write: fn(&Image, &mut Vec<u8>) -> io::Result<()>) -> Vec<u8> {
+        let mut out = Vec::new();
+        write(&image(), &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn ppm() {
+        let mut expected = b"P6\n2 2\n255\n".to_vec();
+        expected.extend([0, 0, 0, 255, 128, 3, 64, 64, 64, 255, 0, 0]);
+        assert_eq!(written(write_ppm), expected);
+
+        assert_eq!(
+            String::from_utf8(written(write_ppm_ascii)).unwrap(),
+            "P3\n2 2 255\n\n0 0 0\n255 128 3\n64 64 64\n255 0 0\n"
+        );
+    }
+
+    #[test]
+    fn pgm() {
+        let mut expected = b"P5\n2 2\n255\n".to_vec();
+        expected.extend([0, 146, 64, 90]);
+        assert_eq!(written(write_pgm), expected);
+    }
+
+    #[test]
+    fn pfm() {
+        let out = written(write_pfm);
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&out[..header.len()], header);
+
+        let floats: Vec<f32> = out[header.len()..]
+            .chunks(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        let image = image();
+        let bottom_up: Vec<f32> = [0, 1, 0, 1]
+            .iter()
+            .zip([1, 1, 0, 0])
+            .flat_map(|(&x, y)| image.get(x, y))
+            .collect();
+        assert_eq!(floats, bottom_up);
+    }
+}
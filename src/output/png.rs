@@ -0,0 +1,324 @@
+use std::io::{self, Write};
+
+use crate::image::Image;
+
+use super::zlib;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BitDepth {
+    #[default]
+    Eight,
+    Sixteen,
+}
+
+impl BitDepth {
+    fn bytes(self) -> usize {
+        match self {
+            Self::Eight => 1,
+            Self::Sixteen => 2,
+        }
+    }
+}
+
+/// Writes an RGB PNG file.
+pub fn write_png(image: &Image, depth: BitDepth, out: &mut impl Write) -> io::Result<()> {
+    let samples = image.pixels().iter().flatten().copied();
+    encode(image.width(), image.height(), 3, depth, samples, out)
+}
+
+/// Writes an RGBA PNG file, with one alpha value in `0..=1` per pixel.
+pub fn write_png_rgba(
+    image: &Image,
+    alpha: &[f32],
+    depth: BitDepth,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    assert_eq!(alpha.len(), image.pixels().len(), "one alpha per pixel");
+    let samples = image
+        .pixels()
+        .iter()
+        .zip(alpha)
+        .flat_map(|(&[r, g, b], &a)| [r, g, b, a]);
+    encode(image.width(), image.height(), 4, depth, samples, out)
+}
+
+/// `samples` are in `0..=1`, clamped if not, row by row from the top.
+fn encode(
+    width: u32,
+    height: u32,
+    channels: usize,
+    depth: BitDepth,
+    samples: impl Iterator<Item = f32>,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let mut raw = Vec::new();
+    for c in samples {
+        let c = c.clamp(0., 1.);
+        match depth {
+            BitDepth::Eight => raw.push((c * 255.).round() as u8),
+            BitDepth::Sixteen => raw.extend(((c * 65535.).round() as u16).to_be_bytes()),
+        }
+    }
+
+    let pixel_size = channels * depth.bytes();
+    let filtered = filter(&raw, width as usize * pixel_size, pixel_size);
+
+    let color_type = if channels == 4 { 6 } else { 2 };
+    let mut header = Vec::new();
+    header.extend(width.to_be_bytes());
+    header.extend(height.to_be_bytes());
+    // Bit depth, color type, then the only compression and filter methods
+    // there are, and no interlacing.
+    header.extend([8 * depth.bytes() as u8, color_type, 0, 0, 0]);
+
+    out.write_all(b"\x89PNG\r\n\x1a\n")?;
+    write_chunk(out, b"IHDR", &header)?;
+    write_chunk(out, b"IDAT", &zlib::compress(&filtered))?;
+    write_chunk(out, b"IEND", &[])?;
+    out.flush()
+}
+
+fn write_chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc = crc32(&[&kind[..], data].concat());
+    out.write_all(&crc.to_be_bytes())
+}
+
+/// Prefixes each row with the filter type that makes it smallest, by the
+/// usual heuristic: the one whose output bytes, taken as signed, have the
+/// lowest absolute sum.
+fn filter(raw: &[u8], row_size: usize, pixel_size: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(raw.len() + raw.len() / row_size.max(1));
+    let zeros = vec![0; row_size];
+    let mut prev: &[u8] = &zeros;
+    for row in raw.chunks(row_size.max(1)) {
+        let best = (0..5)
+            .map(|kind| {
+                let filtered: Vec<u8> = (0..row.len())
+                    .map(|i| {
+                        let a = if i >= pixel_size {
+                            row[i - pixel_size]
+                        } else {
+                            0
+                        };
+                        let b = prev[i];
+                        let c = if i >= pixel_size {
+                            prev[i - pixel_size]
+                        } else {
+                            0
+                        };
+                        row[i].wrapping_sub(predict(kind, a, b, c))
+                    })
+                    .collect();
+                (kind, filtered)
+            })
+            .min_by_key(|(_, filtered)| {
+                filtered
+                    .iter()
+                    .map(|&x| (x as i8).unsigned_abs() as u32)
+                    .sum::<u32>()
+            })
+            .expect("there are filter types");
+        out.push(best.0);
+        out.extend(best.1);
+        prev = row;
+    }
+    out
+}
+
+/// What filter type `kind` predicts a byte to be, from the bytes to its left
+/// (`a`), above (`b`) and above left (`c`).
+fn predict(kind: u8, a: u8, b: u8, c: u8) -> u8 {
+    match kind {
+        0 => 0,
+        1 => a,
+        2 => b,
+        3 => ((a as u16 + b as u16) / 2) as u8,
+        _ => paeth(a, b, c),
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 1 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+/// The checksum of each chunk's type and data.
+pub fn crc32(data: &[u8]) -> u32 {
+    let crc = data.iter().fold(!0u32, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    });
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{output::zlib::tests::inflate, rng::Rng};
+
+    struct Decoded {
+        width: u32,
+        height: u32,
+        depth: u8,
+        color_type: u8,
+        /// Unfiltered, as stored.
+        raw: Vec<u8>,
+    }
+
+    fn decode(png: &[u8]) -> Decoded {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let mut chunks = Vec::new();
+        let mut pos = 8;
+        while pos < png.len() {
+            let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+            let kind = &png[pos + 4..pos + 8];
+            let data = &png[pos + 8..pos + 8 + len];
+            let crc = u32::from_be_bytes(png[pos + 8 + len..pos + 12 + len].try_into().unwrap());
+            assert_eq!(crc, crc32(&png[pos + 4..pos + 8 + len]), "bad CRC");
+            chunks.push((kind, data));
+            pos += 12 + len;
+        }
+        assert_eq!(chunks[0].0, b"IHDR");
+        assert_eq!(chunks.last().unwrap().0, b"IEND");
+
+        let header = chunks[0].1;
+        let width = u32::from_be_bytes(header[0..4].try_into().unwrap());
+        let height = u32::from_be_bytes(header[4..8].try_into().unwrap());
+        let (depth, color_type) = (header[8], header[9]);
+        assert_eq!(&header[10..], [0, 0, 0]);
+
+        let idat: Vec<u8> = chunks
+            .iter()
+            .filter(|(kind, _)| kind == b"IDAT")
+            .flat_map(|(_, data)| data.iter().copied())
+            .collect();
+        let filtered = inflate(&idat);
+
+        let channels = if color_type == 6 { 4 } else { 3 };
+        let pixel_size = channels * depth as usize / 8;
+        let row_size = width as usize * pixel_size;
+        let mut raw: Vec<u8> = Vec::new();
+        for (y, row) in filtered.chunks(row_size + 1).enumerate() {
+            let (kind, row) = (row[0], &row[1..]);
+            for (i, &x) in row.iter().enumerate() {
+                let a = if i >= pixel_size {
+                    raw[raw.len() - pixel_size]
+                } else {
+                    0
+                };
+                let b = if y > 0 { raw[raw.len() - row_size] } else { 0 };
+                let c = if y > 0 && i >= pixel_size {
+                    raw[raw.len() - row_size - pixel_size]
+                } else {
+                    0
+                };
+                raw.push(x.wrapping_add(predict(kind, a, b, c)));
+            }
+        }
+        assert_eq!(raw.len(), row_size * height as usize);
+        Decoded {
+            width,
+            height,
+            depth,
+            color_type,
+            raw,
+        }
+    }
+
+    fn image() -> Image {
+        let mut rng = Rng::new(0);
+        let mut image = Image::new(37, 23);
+        for y in 0..23 {
+            for x in 0..37 {
+                // Smooth gradients, for the filters, and some noise.
+                let noise = rng.next_f64() as f32 * 0.1;
+                image.set(x, y, [x as f32 / 36., y as f32 / 22., noise]);
+            }
+        }
+        image.set(0, 0, [2., -1., 0.5]);
+        image
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+    }
+
+    #[test]
+    fn rgb() {
+        let image = image();
+        let mut out = Vec::new();
+        write_png(&image, BitDepth::Eight, &mut out).unwrap();
+        let png = decode(&out);
+        assert_eq!((png.width, png.height), (37, 23));
+        assert_eq!((png.depth, png.color_type), (8, 2));
+
+        let expected: Vec<u8> = image
+            .pixels()
+            .iter()
+            .flatten()
+            .map(|&c| (c.clamp(0., 1.) * 255.).round() as u8)
+            .collect();
+        assert_eq!(png.raw, expected);
+        assert_eq!(&png.raw[..3], [255, 0, 128]);
+    }
+
+    #[test]
+    fn rgba_16_bit() {
+        let image = image();
+        let alpha: Vec<f32> = (0..image.pixels().len()).map(|i| i as f32 / 850.).collect();
+        let mut out = Vec::new();
+        write_png_rgba(&image, &alpha, BitDepth::Sixteen, &mut out).unwrap();
+        let png = decode(&out);
+        assert_eq!((png.depth, png.color_type), (16, 6));
+
+        let samples: Vec<u16> = png
+            .raw
+            .chunks(2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .collect();
+        let expected: Vec<u16> = image
+            .pixels()
+            .iter()
+            .zip(&alpha)
+            .flat_map(|(&[r, g, b], &a)| [r, g, b, a])
+            .map(|c| (c.clamp(0., 1.) * 65535.).round() as u16)
+            .collect();
+        assert_eq!(samples, expected);
+    }
+}
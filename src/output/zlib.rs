@@ -0,0 +1,408 @@
+//! zlib streams (RFC 1950) of DEFLATE data (RFC 1951), for PNG and EXR.
+//!
+//! Blocks are compressed with LZ77 and the fixed Huffman codes, or stored
+//! as they are if that would be smaller. Dynamic Huffman codes would
+//! compress better, but aren't worth the complexity here.
+
+/// Input is split into blocks of this size. Stored blocks can't be any
+/// larger.
+const BLOCK_SIZE: usize = 65_535;
+const WINDOW_SIZE: usize = 32_768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// How many earlier positions with the same hash are tried, per match.
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Compresses `data` into a zlib stream.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = BitWriter::default();
+    // CMF: deflate with a 32K window. FLG: default compression level, and
+    // a check value making CMF * 256 + FLG a multiple of 31.
+    out.bytes.extend([0x78, 0x9c]);
+
+    let mut matcher = Matcher::new(data);
+    let mut start = 0;
+    loop {
+        let end = (start + BLOCK_SIZE).min(data.len());
+        let last = end == data.len();
+        let symbols = matcher.symbols(start, end);
+        let fixed_bits: usize = symbols.iter().map(Symbol::fixed_bits).sum::<usize>() + 7;
+        // A stored block may need up to 7 bits of padding, then its length
+        // and the data.
+        let stored_bits = 3 + 7 + 32 + 8 * (end - start);
+        if fixed_bits <= stored_bits {
+            out.bits(last as u32, 1);
+            out.bits(1, 2);
+            for symbol in symbols {
+                symbol.write_fixed(&mut out);
+            }
+            write_fixed_code(&mut out, 256);
+        } else {
+            out.bits(last as u32, 1);
+            out.bits(0, 2);
+            out.align();
+            let len = (end - start) as u16;
+            out.bytes.extend(len.to_le_bytes());
+            out.bytes.extend((!len).to_le_bytes());
+            out.bytes.extend(&data[start..end]);
+        }
+        if last {
+            break;
+        }
+        start = end;
+    }
+
+    out.align();
+    let mut bytes = out.bytes;
+    bytes.extend(adler32(data).to_be_bytes());
+    bytes
+}
+
+/// The checksum at the end of a zlib stream.
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65_521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // Sums can't overflow within a chunk this size.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Symbol {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+impl Symbol {
+    /// The size of this symbol with the fixed Huffman codes.
+    fn fixed_bits(&self) -> usize {
+        match *self {
+            Self::Literal(byte) => fixed_code(byte as u16).1 as usize,
+            Self::Match { length, distance } => {
+                let (code, _) = length_code(length);
+                let (dcode, _) = distance_code(distance);
+                fixed_code(257 + code as u16).1 as usize
+                    + LENGTH_EXTRA[code] as usize
+                    + 5
+                    + DISTANCE_EXTRA[dcode] as usize
+            }
+        }
+    }
+
+    fn write_fixed(self, out: &mut BitWriter) {
+        match self {
+            Self::Literal(byte) => write_fixed_code(out, byte as u16),
+            Self::Match { length, distance } => {
+                let (code, extra) = length_code(length);
+                write_fixed_code(out, 257 + code as u16);
+                out.bits(extra as u32, LENGTH_EXTRA[code] as u32);
+
+                let (code, extra) = distance_code(distance);
+                out.bits(reverse(code as u32, 5), 5);
+                out.bits(extra as u32, DISTANCE_EXTRA[code] as u32);
+            }
+        }
+    }
+}
+
+/// The code and its length for a literal/length symbol, with the fixed
+/// Huffman codes.
+fn fixed_code(symbol: u16) -> (u32, u32) {
+    let symbol = symbol as u32;
+    match symbol {
+        0..=143 => (0x30 + symbol, 8),
+        144..=255 => (0x190 + symbol - 144, 9),
+        256..=279 => (symbol - 256, 7),
+        _ => (0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_fixed_code(out: &mut BitWriter, symbol: u16) {
+    // Huffman codes are packed starting from their most significant bit.
+    let (code, len) = fixed_code(symbol);
+    out.bits(reverse(code, len), len);
+}
+
+fn reverse(code: u32, len: u32) -> u32 {
+    code.reverse_bits() >> (32 - len)
+}
+
+/// The index of a match length's code, and the extra bits' value.
+fn length_code(length: u16) -> (usize, u16) {
+    let code = LENGTH_BASE.partition_point(|&base| base <= length) - 1;
+    (code, length - LENGTH_BASE[code])
+}
+
+fn distance_code(distance: u16) -> (usize, u16) {
+    let code = DISTANCE_BASE.partition_point(|&base| base <= distance) - 1;
+    (code, distance - DISTANCE_BASE[code])
+}
+
+/// Finds repeated strings, with hash chains over the last
+/// [`WINDOW_SIZE`] bytes.
+struct Matcher<'a> {
+    data: &'a [u8],
+    /// The most recent position with each hash, plus one; zero for none.
+    head: Vec<usize>,
+    /// The previous position with the same hash, plus one, indexed by
+    /// position modulo the window size.
+    prev: Vec<usize>,
+    /// Positions before this have been hashed.
+    hashed: usize,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            head: vec![0; 1 << HASH_BITS],
+            prev: vec![0; WINDOW_SIZE],
+            hashed: 0,
+        }
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let [a, b, c] = [0, 1, 2].map(|i| self.data[pos + i] as u32);
+        let x = (a << 16 | b << 8 | c).wrapping_mul(0x9e37_79b1);
+        (x >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, pos: usize) {
+        if pos + MIN_MATCH > self.data.len() {
+            return;
+        }
+        let hash = self.hash(pos);
+        self.prev[pos % WINDOW_SIZE] = self.head[hash];
+        self.head[hash] = pos + 1;
+    }
+
+    /// The longest earlier match for the bytes at `pos`, as its length and
+    /// distance. Matches may reach back into earlier blocks, but not past
+    /// `end`.
+    fn longest_match(&self, pos: usize, end: usize) -> Option<(usize, usize)> {
+        if pos + MIN_MATCH > end {
+            return None;
+        }
+        let max_len = MAX_MATCH.min(end - pos);
+        let mut best: Option<(usize, usize)> = None;
+        let mut candidate = self.head[self.hash(pos)];
+        for _ in 0..MAX_CHAIN {
+            if candidate == 0 {
+                break;
+            }
+            let start = candidate - 1;
+            if start >= pos || pos - start > WINDOW_SIZE {
+                break;
+            }
+            let len = self.data[start..]
+                .iter()
+                .zip(&self.data[pos..pos + max_len])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len >= MIN_MATCH && best.is_none_or(|(best_len, _)| len > best_len) {
+                best = Some((len, pos - start));
+                if len == max_len {
+                    break;
+                }
+            }
+            candidate = self.prev[start % WINDOW_SIZE];
+        }
+        best
+    }
+
+    /// Greedily encodes `data[start..end]`.
+    fn symbols(&mut self, start: usize, end: usize) -> Vec<Symbol> {
+        let mut symbols = Vec::new();
+        let mut pos = start;
+        while pos < end {
+            while self.hashed < pos {
+                self.insert(self.hashed);
+                self.hashed += 1;
+            }
+            match self.longest_match(pos, end) {
+                Some((length, distance)) => {
+                    symbols.push(Symbol::Match {
+                        length: length as u16,
+                        distance: distance as u16,
+                    });
+                    pos += length;
+                }
+                None => {
+                    symbols.push(Symbol::Literal(self.data[pos]));
+                    pos += 1;
+                }
+            }
+        }
+        symbols
+    }
+}
+
+/// Packs bits starting from the least significant bit of each byte.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    len: u32,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, len: u32) {
+        self.buffer |= (value as u64) << self.len;
+        self.len += len;
+        while self.len >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    /// Pads to the next byte boundary with zeros.
+    fn align(&mut self) {
+        if self.len > 0 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer = 0;
+            self.len = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    /// Decompresses a zlib stream with stored and fixed-code blocks, which
+    /// is all [`compress`] writes.
+    pub fn inflate(stream: &[u8]) -> Vec<u8> {
+        assert_eq!(stream[0] & 0xf, 8, "not deflate");
+        assert_eq!(u16::from_be_bytes([stream[0], stream[1]]) % 31, 0);
+
+        let mut reader = BitReader {
+            bytes: &stream[2..],
+            pos: 0,
+        };
+        let mut out = Vec::new();
+        loop {
+            let last = reader.bits(1) == 1;
+            match reader.bits(2) {
+                0 => {
+                    reader.pos = reader.pos.div_ceil(8) * 8;
+                    let len = reader.bits(16) as usize;
+                    let nlen = reader.bits(16) as usize;
+                    assert_eq!(len, !nlen & 0xffff);
+                    for _ in 0..len {
+                        out.push(reader.bits(8) as u8);
+                    }
+                }
+                1 => loop {
+                    let symbol = reader.fixed_symbol();
+                    if symbol < 256 {
+                        out.push(symbol as u8);
+                        continue;
+                    } else if symbol == 256 {
+                        break;
+                    }
+                    let code = symbol as usize - 257;
+                    let length = LENGTH_BASE[code] as usize
+                        + reader.bits(LENGTH_EXTRA[code] as u32) as usize;
+                    let code = reverse(reader.bits(5), 5) as usize;
+                    let distance = DISTANCE_BASE[code] as usize
+                        + reader.bits(DISTANCE_EXTRA[code] as u32) as usize;
+                    for _ in 0..length {
+                        out.push(out[out.len() - distance]);
+                    }
+                },
+                kind => panic!("unsupported block type {kind}"),
+            }
+            if last {
+                break;
+            }
+        }
+
+        let end = 2 + reader.pos.div_ceil(8);
+        let checksum = u32::from_be_bytes(stream[end..end + 4].try_into().unwrap());
+        assert_eq!(checksum, adler32(&out));
+        assert_eq!(end + 4, stream.len(), "trailing data");
+        out
+    }
+
+    struct BitReader<'a> {
+        bytes: &'a [u8],
+        /// In bits.
+        pos: usize,
+    }
+
+    impl BitReader<'_> {
+        fn bits(&mut self, len: u32) -> u32 {
+            let mut value = 0;
+            for i in 0..len {
+                let bit = self.bytes[self.pos / 8] >> (self.pos % 8) & 1;
+                value |= (bit as u32) << i;
+                self.pos += 1;
+            }
+            value
+        }
+
+        fn fixed_symbol(&mut self) -> u16 {
+            // Read a code most significant bit first, until it's one of the
+            // fixed codes of that length.
+            let mut code = 0;
+            for len in 1..=9 {
+                code = code << 1 | self.bits(1);
+                let symbol = (0..288).find(|&s| fixed_code(s) == (code, len));
+                if let Some(symbol) = symbol {
+                    return symbol;
+                }
+            }
+            panic!("invalid code {code:b}");
+        }
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(adler32(&[0xff; 100_000]), 0x149a_302c);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut rng = Rng::new(0);
+        let noise: Vec<u8> = (0..200_000).map(|_| rng.next_u64() as u8).collect();
+        let text = "the quick brown fox jumps over the lazy dog. ".repeat(5000);
+        let runs: Vec<u8> = (0..150_000u32).map(|i| (i / 1000) as u8).collect();
+
+        for data in [&b""[..], b"a", b"aaaa", &noise, text.as_bytes(), &runs] {
+            let compressed = compress(data);
+            assert_eq!(inflate(&compressed), data);
+        }
+
+        // Repetitive data compresses; noise is stored with little overhead.
+        assert!(compress(text.as_bytes()).len() < text.len() / 20);
+        assert!(compress(&noise).len() < noise.len() + 100);
+    }
+}
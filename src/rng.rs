@@ -0,0 +1,61 @@
+/// A small seedable random number generator (xorshift64*), so that renders
+/// are reproducible for a given seed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck at zero, and similar seeds give similar early
+        // outputs, so scramble the seed first.
+        let state = splitmix(seed);
+        Self {
+            state: if state == 0 { 1 } else { state },
+        }
+    }
+
+    /// A generator for one of several independent streams, e.g. one per
+    /// pixel, all derived from the same seed.
+    pub fn stream(seed: u64, stream: u64) -> Self {
+        Self::new(splitmix(seed) ^ stream)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Uniform in `0..1`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+fn splitmix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deterministic() {
+        let sample = |mut rng: Rng| -> Vec<u64> { (0..4).map(|_| rng.next_u64()).collect() };
+        assert_eq!(sample(Rng::new(1)), sample(Rng::new(1)));
+        assert_ne!(sample(Rng::new(1)), sample(Rng::new(2)));
+        assert_ne!(sample(Rng::stream(1, 0)), sample(Rng::stream(1, 1)));
+
+        let mut rng = Rng::new(0);
+        let mean = (0..10_000).map(|_| rng.next_f64()).sum::<f64>() / 10_000.;
+        assert!((mean - 0.5).abs() < 0.02, "{mean}");
+    }
+}
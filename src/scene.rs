@@ -1,26 +1,64 @@
-use std::{
-    any::{self, Any, TypeId},
-    ops::Deref,
-};
+mod aov;
+mod builder;
+mod checkpoint;
+mod debug;
+mod progressive;
+mod region;
+mod render;
+
+use std::path::Path;
 
 use crate::{
     color::Color,
-    error::Result,
-    objects::{Object, Plane, Sphere},
-    sdl,
+    error::{Error, Result},
+    image::Image,
+    objects::Object,
+    sdl::{self, FromNode, NodeError, SceneFile, ValidationError},
     vec3::Vec3,
 };
 
+pub use aov::{Aov, Pass, Passes};
+pub(crate) use builder::check_geometry;
+pub use builder::{BuildError, SceneBuilder};
+pub use checkpoint::{scene_hash, Checkpoint};
+pub use debug::DebugShading;
+pub use progressive::{Accumulator, StopCondition};
+pub use region::Region;
+pub use render::{Integrator, RenderSettings};
+
 pub struct Scene {
     objects: Vec<Box<dyn Object>>,
-    camera_position: Vec3,
-    camera_up: Vec3,
-    camera_right: Vec3,
+    camera: Camera,
     focal_distance: f64,
     screen_width: u32,
     screen_height: u32,
-    light_source: Vec3,
-    light_color: Color,
+    /// The width of a pixel, in world units.
+    pixel_size: f64,
+    /// The part of the screen that's rendered.
+    region: Region,
+    light: Light,
+}
+
+pub struct Camera {
+    pub position: Vec3,
+    // todo: use these, instead of assuming an axis-aligned camera
+    pub up: Vec3,
+    pub right: Vec3,
+}
+
+pub struct Light {
+    pub position: Vec3,
+    pub color: Color,
+    /// A multiple of `color`. Above 1, lit surfaces can be brighter than
+    /// white, which only HDR, EXR and PFM output keep.
+    pub intensity: f64,
+}
+
+impl Light {
+    /// The light's color and intensity together, as linear RGB.
+    pub fn radiance(&self) -> Vec3 {
+        render::to_vec(self.color) * self.intensity
+    }
 }
 
 // todo: cross product of up and right
@@ -28,103 +66,203 @@ pub struct Scene {
 const FORWARD: Vec3 = Vec3::new(0., 0., 1.);
 
 impl Scene {
+    pub fn builder() -> SceneBuilder {
+        SceneBuilder::default()
+    }
+
+    /// Any includes are resolved relative to the current directory.
     pub fn from_sdl(text: &str) -> Result<Self> {
-        let tree: sdl::Node = text.parse()?;
-        Ok(Self {
-            camera_position: tree.get_path("camera position")?.try_into()?,
-            camera_up: tree.get_path("camera up")?.try_into()?,
-            camera_right: tree.get_path("camera right")?.try_into()?,
-
-            focal_distance: tree.get_path("focal-distance")?.try_into()?,
-            screen_width: tree.get_path("screen width")?.try_into()?,
-            screen_height: tree.get_path("screen height")?.try_into()?,
-
-            light_source: tree.get_path("lights light position")?.try_into()?,
-            light_color: tree.get_path("lights light color")?.try_into()?,
-
-            objects: tree.get_path("objects")?.try_into()?,
-        })
-    }
-
-    /// Prints a PPM file to stdout.
-    // todo: render to a buffer, and then serialize the buffer to stdout.
-    // this'll let you refactor the PPM code into its own module.
-    pub fn render(&self) {
-        println!("P3");
-        println!("{} {} 255", self.screen_width, self.screen_height);
-        println!();
-
-        // iterate over all pixels in the screen
-        for y in 0..self.screen_height {
-            for x in 0..self.screen_width {
-                let color = self.pixel_color(x, y);
-                println!("{}", color.ppm());
-            }
+        let mut tree: sdl::Node = text.parse()?;
+        sdl::resolve_includes(&mut tree, Path::new("."))?;
+        Self::from_tree(tree)
+    }
+
+    pub fn from_sdl_file(path: &Path) -> Result<Self> {
+        Self::from_tree(sdl::parse_file(path)?)
+    }
+
+    /// A scene in JSON, with the same structure as SDL.
+    pub fn from_json_file(path: &Path) -> Result<Self> {
+        Self::from_tree(sdl::parse_json_file(path)?)
+    }
+
+    /// Builds a scene from a parsed tree, whose includes have already been
+    /// resolved. Any warnings are dropped; see
+    /// [`Self::from_tree_with_warnings`].
+    pub fn from_tree(tree: sdl::Node) -> Result<Self> {
+        Self::from_tree_with_warnings(tree).map(|(scene, _)| scene)
+    }
+
+    /// Like [`Self::from_tree`], but also returns what was wrong with the
+    /// tree without stopping it from loading, e.g. unknown keys.
+    pub fn from_tree_with_warnings(mut tree: sdl::Node) -> Result<(Self, Vec<NodeError>)> {
+        sdl::expand(&mut tree).map_err(Error::Reference)?;
+        let warnings = sdl::validate(&mut tree)?;
+        let file = SceneFile::from_node(&tree).map_err(|e| ValidationError(vec![e]))?;
+        file.check_geometry(&tree).map_err(Error::Geometry)?;
+        let scene = Self {
+            objects: file.objects,
+            camera: file.camera,
+            focal_distance: file.focal_distance,
+            screen_width: file.screen.width,
+            screen_height: file.screen.height,
+            pixel_size: 1.,
+            region: Region::whole(file.screen.width, file.screen.height),
+            light: file.lights.light,
+        };
+        Ok((scene, warnings))
+    }
+
+    /// The width of the rendered image: the region's, if one is set.
+    pub fn width(&self) -> u32 {
+        self.region.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.region.height()
+    }
+
+    /// The whole screen, whatever the region.
+    pub fn screen(&self) -> Region {
+        Region::whole(self.screen_width, self.screen_height)
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Renders just part of the screen, framed as it is in the whole image.
+    pub fn set_region(&mut self, region: Region) -> std::result::Result<(), String> {
+        if !self.screen().contains(&region) {
+            return Err(format!(
+                "region {region} is outside the {}x{} screen",
+                self.screen_width, self.screen_height
+            ));
         }
+        self.region = region;
+        Ok(())
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn focal_distance(&self) -> f64 {
+        self.focal_distance
+    }
+
+    pub fn light(&self) -> &Light {
+        &self.light
+    }
+
+    pub fn objects(&self) -> &[Box<dyn Object>] {
+        &self.objects
+    }
+
+    /// Renders at a different resolution, covering the same width of the
+    /// scene. If the aspect ratio changes, the height covered changes with
+    /// it. Any region is reset to the whole screen.
+    pub fn set_resolution(&mut self, width: u32, height: u32) {
+        assert!(width > 0 && height > 0, "empty resolution {width}x{height}");
+        self.pixel_size *= self.screen_width as f64 / width as f64;
+        self.screen_width = width;
+        self.screen_height = height;
+        self.region = self.screen();
+    }
+
+    /// Renders one sample per pixel, with direct lighting.
+    pub fn render(&self) -> Image {
+        self.render_with(&RenderSettings::default())
     }
 
     fn top_left_pixel(&self) -> Vec3 {
         // find the center pixel of the screen
-        let adjust = [0.5, -0.5, 0.].into();
-        let center = self.camera_position + self.focal_distance * FORWARD + adjust;
+        let s = self.pixel_size;
+        let adjust = [0.5 * s, -0.5 * s, 0.].into();
+        let center = self.camera.position + self.focal_distance * FORWARD + adjust;
 
         // compute the corner of the screen
-        let dx = -(self.screen_width as f64) / 2.;
-        let dy = self.screen_height as f64 / 2.;
-        let top_left = center + [dx, dy, 0.].into();
-
-        top_left
+        let dx = -(self.screen_width as f64) * s / 2.;
+        let dy = self.screen_height as f64 * s / 2.;
+        center + [dx, dy, 0.].into()
     }
 
-    fn pixel_color(&self, x: u32, y: u32) -> Color {
+    /// The ray from the camera through a point on the screen, in pixel
+    /// coordinates: `(0, 0)` is the center of the top left pixel.
+    fn primary_ray(&self, x: f64, y: f64) -> Ray {
         let top_left = self.top_left_pixel();
-
-        let x = x as f64;
-        let y = y as f64;
-        let p = top_left + [x, -y, 0.].into();
+        let s = self.pixel_size;
+        let p = top_left + [x * s, -y * s, 0.].into();
 
         // compute the dir'n of the ray
-        let start = self.camera_position;
+        let start = self.camera.position;
         let direction = p - start;
-        let ray = Ray { start, direction };
-
-        if let Some((obj, p)) = self.cast(ray, f64::MAX) {
-            // cast another ray, towards the light source
-            let path = self.light_source - p;
-            let ray = Ray {
-                // TODO: this feels like a hack.
-                // How else can we avoid hitting the current sphere
-                // when we cast a ray? Maybe we skip it somehow?
-                start: p + path.normalize() * 0.1,
-                direction: path,
-            };
-            let max_dist = path.norm();
-
-            let intercepted = self.cast(ray, max_dist).is_some();
-            if intercepted {
+        Ray { start, direction }
+    }
+
+    /// Lights whatever `ray` hits directly from the light source, with hard
+    /// shadows. The result isn't clamped, so it can be brighter than white.
+    fn direct(&self, ray: Ray) -> Vec3 {
+        self.shade(self.closest_hit(ray, f64::MAX))
+    }
+
+    /// [`Self::direct`], for a hit that's already been found.
+    fn shade(&self, hit: Option<(usize, Vec3)>) -> Vec3 {
+        if let Some((index, p)) = hit {
+            let obj = &self.objects[index];
+            let path = self.light.position - p;
+            if self.in_shadow(p, &mut 0) {
                 // hidden in shadow
-                Color::BLACK
+                Vec3::ZERO
             } else {
                 // compute a color value
                 // The "insides" of a surface should also be visible --
                 // hence the .abs() here.
                 let brightness = (path.normalize() * obj.normal(p)).abs();
-                let color = self
-                    .light_color
-                    .direct_product(obj.color())
-                    .scale(brightness);
-                color
+                self.light
+                    .radiance()
+                    .direct_product(render::to_vec(obj.color()))
+                    * brightness
             }
         } else {
-            Color::BLACK
+            Vec3::ZERO
         }
     }
 
-    fn cast(&self, ray: Ray, max_dist: f64) -> Option<(&dyn Object, Vec3)> {
+    /// Whether something lies between `p` and the light.
+    fn in_shadow(&self, p: Vec3, tests: &mut usize) -> bool {
+        // cast another ray, towards the light source
+        let path = self.light.position - p;
+        let ray = Ray {
+            // TODO: this feels like a hack.
+            // How else can we avoid hitting the current sphere
+            // when we cast a ray? Maybe we skip it somehow?
+            start: p + path.normalize() * 0.1,
+            direction: path,
+        };
+        let max_dist = path.norm();
+
+        self.closest_hit_counting(ray, max_dist, tests).is_some()
+    }
+
+    /// The index of the closest object `ray` hits, and where.
+    fn closest_hit(&self, ray: Ray, max_dist: f64) -> Option<(usize, Vec3)> {
+        self.closest_hit_counting(ray, max_dist, &mut 0)
+    }
+
+    /// [`Self::closest_hit`], adding the number of intersection tests to
+    /// `tests`.
+    fn closest_hit_counting(
+        &self,
+        ray: Ray,
+        max_dist: f64,
+        tests: &mut usize,
+    ) -> Option<(usize, Vec3)> {
         let dist = |p: Vec3| (p - ray.start).norm_squared().sqrt();
 
         let mut closest_hit = None;
-        for obj in &self.objects {
+        for (index, obj) in self.objects.iter().enumerate() {
+            *tests += 1;
             if let Some(p) = obj.hit_test(ray) {
                 if dist(p) > max_dist {
                     continue;
@@ -135,7 +273,7 @@ impl Scene {
                     None => true,
                 };
                 if is_closer {
-                    closest_hit = Some((obj.deref(), p));
+                    closest_hit = Some((index, p));
                 }
             }
         }
@@ -148,3 +286,43 @@ pub struct Ray {
     pub start: Vec3,
     pub direction: Vec3,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::Sphere;
+
+    /// A green sphere at the origin, seen from 20 units in front of it on a
+    /// 16x12 screen. Tests add to it, or replace its parts.
+    pub(super) fn test_scene() -> SceneBuilder {
+        Scene::builder()
+            .camera(Camera {
+                position: Vec3::new(0., 0., -20.),
+                up: Vec3::Y_AXIS,
+                right: Vec3::X_AXIS,
+            })
+            .focal_distance(10.)
+            .screen(16, 12)
+            .light(Light {
+                position: Vec3::new(5., 0., -20.),
+                color: Color::WHITE,
+                intensity: 1.,
+            })
+            .add(Sphere {
+                color: Color::GREEN,
+                center: Vec3::ZERO,
+                radius: 5.,
+            })
+    }
+
+    #[test]
+    fn warnings() {
+        let text =
+            include_str!("../scene.sdl").replace("focal-distance", "shine 1\n    focal-distance");
+        let tree: sdl::Node = text.parse().unwrap();
+        let (_, warnings) = Scene::from_tree_with_warnings(tree).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].path, "scene > shine");
+        assert!(warnings[0].message.starts_with("unknown key \"shine\""));
+    }
+}
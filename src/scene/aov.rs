@@ -0,0 +1,252 @@
+use crate::{image::Image, named::named_variants, vec3::Vec3};
+
+use super::{render, Ray, RenderSettings, Scene};
+
+/// An auxiliary render pass ("arbitrary output variable"): something about
+/// the surface seen through each pixel, other than its lit color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Aov {
+    /// Distance from the camera, or infinity where nothing is hit.
+    Depth,
+    /// World-space surface normal, as the object reports it.
+    Normal,
+    /// Surface color, without lighting.
+    Albedo,
+    /// Index of the object in the scene, or -1 where nothing is hit.
+    Object,
+    /// World-space hit position.
+    Position,
+}
+
+named_variants! {
+    Aov, "pass" {
+        Depth => "depth",
+        Normal => "normal",
+        Albedo => "albedo",
+        Object => "object",
+        Position => "position",
+    }
+}
+
+impl Aov {
+    /// The pass's channel names, in OpenEXR's conventions.
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Self::Depth => &["Z"],
+            Self::Normal => &["N.X", "N.Y", "N.Z"],
+            Self::Albedo => &["albedo.R", "albedo.G", "albedo.B"],
+            Self::Object => &["id"],
+            Self::Position => &["P.X", "P.Y", "P.Z"],
+        }
+    }
+
+    /// The value where nothing is hit.
+    fn background(self) -> f32 {
+        match self {
+            Self::Depth => f32::INFINITY,
+            Self::Object => -1.,
+            Self::Normal | Self::Albedo | Self::Position => 0.,
+        }
+    }
+}
+
+/// One AOV for the whole image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pass {
+    aov: Aov,
+    width: u32,
+    height: u32,
+    /// Each pixel's channels together, row by row from the top left.
+    values: Vec<f32>,
+}
+
+impl Pass {
+    pub fn aov(&self) -> Aov {
+        self.aov
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> &[f32] {
+        assert!(
+            x < self.width && y < self.height,
+            "({x}, {y}) out of bounds"
+        );
+        let n = self.aov.channels().len();
+        let i = (y as usize * self.width as usize + x as usize) * n;
+        &self.values[i..i + n]
+    }
+
+    /// The values of one of [`Aov::channels`], for every pixel.
+    pub fn channel(&self, index: usize) -> Vec<f32> {
+        let n = self.aov.channels().len();
+        self.values.iter().skip(index).step_by(n).copied().collect()
+    }
+
+    /// The values as they are, in gray for single-channel passes. Most
+    /// image formats will clamp them, so this is mostly useful with PFM,
+    /// HDR or EXR.
+    pub fn to_image(&self) -> Image {
+        let mut image = Image::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let rgb = match *self.get(x, y) {
+                    [v] => [v; 3],
+                    [r, g, b] => [r, g, b],
+                    _ => unreachable!("passes have one or three channels"),
+                };
+                image.set(x, y, rgb);
+            }
+        }
+        image
+    }
+}
+
+/// The lit image, and whichever AOVs were asked for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Passes {
+    pub beauty: Image,
+    pub aovs: Vec<Pass>,
+}
+
+impl Scene {
+    /// Renders the image, and the AOVs for the surface seen through the
+    /// center of each pixel. They're the same however many samples the
+    /// image has, so that e.g. object indices aren't blurred together.
+    pub fn render_passes(&self, settings: &RenderSettings, aovs: &[Aov]) -> Passes {
+        let pixels = self.map_pixels(settings.threads, |x, y| {
+            let (ray, hit) = self.center_hit(x, y);
+            // Without samples to average, the image is lit from the same hit.
+            let rgb = if settings.center_only() {
+                render::to_rgb(self.shade(hit))
+            } else {
+                self.render_pixel(x, y, settings)
+            };
+            (rgb, self.aov_values(ray, hit, aovs))
+        });
+
+        let width = self.width();
+        let mut beauty = Image::new(width, self.height());
+        let mut values = Vec::with_capacity(pixels.len());
+        for (i, (rgb, pixel_values)) in pixels.into_iter().enumerate() {
+            let i = i as u32;
+            beauty.set(i % width, i / width, rgb);
+            values.push(pixel_values);
+        }
+        self.finish_debug(&mut beauty, settings);
+        Passes {
+            beauty,
+            aovs: self.collect_passes(aovs, values),
+        }
+    }
+
+    /// Renders just the AOVs, e.g. to go with an image rendered
+    /// progressively.
+    pub fn render_aovs(&self, threads: usize, aovs: &[Aov]) -> Vec<Pass> {
+        let values = self.map_pixels(threads, |x, y| {
+            let (ray, hit) = self.center_hit(x, y);
+            self.aov_values(ray, hit, aovs)
+        });
+        self.collect_passes(aovs, values)
+    }
+
+    /// Sorts each pixel's [`Self::aov_values`] into passes.
+    fn collect_passes(&self, aovs: &[Aov], pixels: Vec<Vec<f32>>) -> Vec<Pass> {
+        let mut passes: Vec<Pass> = aovs
+            .iter()
+            .map(|&aov| Pass {
+                aov,
+                width: self.width(),
+                height: self.height(),
+                values: Vec::with_capacity(pixels.len() * aov.channels().len()),
+            })
+            .collect();
+        for values in pixels {
+            let mut values = values.into_iter();
+            for pass in &mut passes {
+                let n = pass.aov.channels().len();
+                pass.values.extend(values.by_ref().take(n));
+            }
+        }
+        passes
+    }
+
+    /// The values of each AOV in turn, for the pixel whose center `ray`
+    /// passes through, given what it hit.
+    fn aov_values(&self, ray: Ray, hit: Option<(usize, Vec3)>, aovs: &[Aov]) -> Vec<f32> {
+        let mut values = Vec::new();
+        for &aov in aovs {
+            let Some((index, p)) = hit else {
+                values.extend(aov.channels().iter().map(|_| aov.background()));
+                continue;
+            };
+            let object = &self.objects[index];
+            let vec = |v: Vec3| [v.x(), v.y(), v.z()].map(|c| c as f32);
+            match aov {
+                Aov::Depth => values.push((p - ray.start).norm() as f32),
+                Aov::Normal => values.extend(vec(object.normal(p))),
+                Aov::Albedo => values.extend(object.color().to_rgb()),
+                Aov::Object => values.push(index as f32),
+                Aov::Position => values.extend(vec(p)),
+            }
+        }
+        values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::Color, objects::Plane, scene::tests::test_scene};
+
+    #[test]
+    fn passes() {
+        let scene = test_scene()
+            .add(Plane {
+                anchor: Vec3::new(0., -3., 0.),
+                normal: Vec3::Y_AXIS,
+                color: Color::RED,
+            })
+            .build()
+            .unwrap();
+        let settings = RenderSettings {
+            threads: 2,
+            ..RenderSettings::default()
+        };
+        let passes = scene.render_passes(&settings, &Aov::ALL);
+        assert_eq!(passes.beauty, scene.render());
+        assert_eq!(passes.aovs, scene.render_aovs(1, &Aov::ALL));
+        let [depth, normal, albedo, object, position] = &passes.aovs[..] else {
+            panic!("expected every pass");
+        };
+        assert_eq!(depth.aov(), Aov::Depth);
+
+        // The rays through the middle pixels go nearly straight down the z
+        // axis, to the front of the sphere.
+        let [d] = *depth.get(7, 5) else { panic!() };
+        assert!((d - 15.).abs() < 0.2, "{d}");
+        assert!(normal.get(7, 5)[2] < -0.9);
+        assert_eq!(albedo.get(7, 5), [0., 1., 0.]);
+        assert_eq!(object.get(7, 5), [0.]);
+        let [px, py, pz] = *position.get(7, 5) else {
+            panic!()
+        };
+        let distance = Vec3::new(px as f64, py as f64, pz as f64 + 20.).norm();
+        assert!((distance as f32 - d).abs() < 1e-4);
+
+        // The bottom row sees the plane; the top corner sees nothing.
+        assert_eq!(object.get(0, 11), [1.]);
+        assert_eq!(normal.get(0, 11), [0., 1., 0.]);
+        assert!((position.get(0, 11)[1] + 3.).abs() < 1e-4);
+        assert_eq!(object.get(0, 0), [-1.]);
+        assert_eq!(depth.get(0, 0), [f32::INFINITY]);
+        assert_eq!(albedo.channel(0)[0], 0.);
+        assert_eq!(depth.to_image().get(0, 0), [f32::INFINITY; 3]);
+    }
+
+    #[test]
+    fn names() {
+        for aov in Aov::ALL {
+            assert_eq!(aov.name().parse(), Ok(aov));
+        }
+        assert!("color".parse::<Aov>().is_err());
+    }
+}
@@ -0,0 +1,267 @@
+use std::{error::Error as StdError, fmt};
+
+use crate::objects::Object;
+
+use super::{Camera, Light, Region, Scene};
+
+/// Builds a [`Scene`] in code, rather than from a file.
+///
+/// ```
+/// use ray_tracer::{color::Color, objects::Sphere, scene::{Camera, Light}, vec3::Vec3, Scene};
+///
+/// let scene = Scene::builder()
+///     .camera(Camera {
+///         position: Vec3::new(0., 0., -20.),
+///         up: Vec3::Y_AXIS,
+///         right: Vec3::X_AXIS,
+///     })
+///     .focal_distance(10.)
+///     .screen(64, 48)
+///     .light(Light {
+///         position: Vec3::new(5., 0., -20.),
+///         color: Color::WHITE,
+///         intensity: 1.,
+///     })
+///     .add(Sphere {
+///         color: Color::GREEN,
+///         center: Vec3::ZERO,
+///         radius: 10.,
+///     })
+///     .build()?;
+/// # Ok::<(), ray_tracer::Error>(())
+/// ```
+#[derive(Default)]
+pub struct SceneBuilder {
+    camera: Option<Camera>,
+    focal_distance: Option<f64>,
+    screen: Option<(u32, u32)>,
+    light: Option<Light>,
+    objects: Vec<Box<dyn Object>>,
+}
+
+impl SceneBuilder {
+    pub fn camera(mut self, camera: Camera) -> Self {
+        self.camera = Some(camera);
+        self
+    }
+
+    /// The distance from the camera to the screen.
+    pub fn focal_distance(mut self, distance: f64) -> Self {
+        self.focal_distance = Some(distance);
+        self
+    }
+
+    /// The size of the rendered image, in pixels.
+    pub fn screen(mut self, width: u32, height: u32) -> Self {
+        self.screen = Some((width, height));
+        self
+    }
+
+    pub fn light(mut self, light: Light) -> Self {
+        self.light = Some(light);
+        self
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn add(mut self, object: impl Object) -> Self {
+        self.objects.push(Box::new(object));
+        self
+    }
+
+    /// Checks that everything has been set, and that the geometry can be
+    /// rendered.
+    pub fn build(self) -> Result<Scene, BuildError> {
+        let camera = self.camera.ok_or(BuildError::Missing("camera"))?;
+        let focal_distance = self
+            .focal_distance
+            .ok_or(BuildError::Missing("focal distance"))?;
+        let (width, height) = self.screen.ok_or(BuildError::Missing("screen"))?;
+        let light = self.light.ok_or(BuildError::Missing("light"))?;
+        check_geometry(focal_distance, (width, height), &self.objects)?;
+
+        Ok(Scene {
+            objects: self.objects,
+            camera,
+            focal_distance,
+            screen_width: width,
+            screen_height: height,
+            pixel_size: 1.,
+            region: Region::whole(width, height),
+            light,
+        })
+    }
+}
+
+/// Checks for geometry that can't be rendered, whether the scene was built in
+/// code or loaded from a file.
+pub(crate) fn check_geometry(
+    focal_distance: f64,
+    (width, height): (u32, u32),
+    objects: &[Box<dyn Object>],
+) -> Result<(), BuildError> {
+    if focal_distance <= 0. || !focal_distance.is_finite() {
+        return Err(BuildError::InvalidFocalDistance(focal_distance));
+    }
+    if width == 0 || height == 0 {
+        return Err(BuildError::EmptyScreen { width, height });
+    }
+    for (index, object) in objects.iter().enumerate() {
+        object
+            .check()
+            .map_err(|message| BuildError::InvalidObject { index, message })?;
+    }
+    Ok(())
+}
+
+/// Why a [`SceneBuilder`] couldn't build its scene.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildError {
+    /// A required part of the scene was never set.
+    Missing(&'static str),
+    InvalidFocalDistance(f64),
+    EmptyScreen {
+        width: u32,
+        height: u32,
+    },
+    /// The object at `index`, in the order they were added, failed its
+    /// geometry check.
+    InvalidObject {
+        index: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(part) => write!(f, "scene has no {part}"),
+            Self::InvalidFocalDistance(d) => {
+                write!(f, "focal distance must be positive, found {d}")
+            }
+            Self::EmptyScreen { width, height } => {
+                write!(f, "screen must not be empty, found {width}x{height}")
+            }
+            Self::InvalidObject { index, message } => write!(f, "object {index}: {message}"),
+        }
+    }
+}
+
+impl StdError for BuildError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        color::Color,
+        objects::{Plane, Sphere},
+        vec3::Vec3,
+    };
+
+    fn builder() -> SceneBuilder {
+        Scene::builder()
+            .camera(Camera {
+                position: Vec3::new(0., 0., -20.),
+                up: Vec3::Y_AXIS,
+                right: Vec3::X_AXIS,
+            })
+            .focal_distance(10.)
+            .screen(16, 12)
+            .light(Light {
+                position: Vec3::new(5., 0., -20.),
+                color: Color::new(255, 200, 255),
+                intensity: 1.,
+            })
+    }
+
+    #[test]
+    fn same_as_sdl() {
+        let built = builder()
+            .add(Sphere {
+                color: Color::GREEN,
+                center: Vec3::new(-5., 2.5, -2.),
+                radius: 10.,
+            })
+            .add(Plane {
+                anchor: Vec3::new(0., 0., 10.),
+                normal: Vec3::Z_AXIS,
+                color: Color::WHITE,
+            })
+            .build()
+            .unwrap();
+        let parsed = Scene::from_sdl(
+            "scene {
+    camera {
+        position 0 0 -20
+    }
+    focal-distance 10
+    screen {
+        width 16
+        height 12
+    }
+    lights {
+        light {
+            position 5 0 -20
+            color 255 200 255
+        }
+    }
+    objects {
+        sphere {
+            color 0 255 0
+            center -5 2.5 -2
+            radius 10
+        }
+        plane {
+            anchor 0 0 10
+            normal 0 0 1
+        }
+    }
+}",
+        )
+        .unwrap();
+        assert_eq!(built.render(), parsed.render());
+    }
+
+    #[test]
+    fn validation() {
+        let err = |builder: SceneBuilder| builder.build().err().unwrap();
+
+        assert_eq!(
+            err(Scene::builder().screen(1, 1)),
+            BuildError::Missing("camera")
+        );
+        assert_eq!(
+            err(builder().screen(0, 12)),
+            BuildError::EmptyScreen {
+                width: 0,
+                height: 12
+            }
+        );
+        assert_eq!(
+            err(builder().focal_distance(-1.)),
+            BuildError::InvalidFocalDistance(-1.)
+        );
+
+        let e = err(builder()
+            .add(Sphere {
+                color: Color::RED,
+                center: Vec3::ZERO,
+                radius: 1.,
+            })
+            .add(Plane {
+                anchor: Vec3::ZERO,
+                normal: Vec3::ZERO,
+                color: Color::RED,
+            }));
+        assert_eq!(e.to_string(), "object 1: normal must not be zero");
+    }
+
+    #[test]
+    fn into_error() {
+        fn build(builder: SceneBuilder) -> crate::Result<Scene> {
+            Ok(builder.build()?)
+        }
+        assert!(build(builder()).is_ok());
+        let e = build(builder().screen(0, 0)).err().unwrap();
+        assert!(matches!(e, crate::Error::Build(_)), "{e:?}");
+    }
+}
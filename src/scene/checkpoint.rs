@@ -0,0 +1,232 @@
+//! Saves a progressive render so that it can be continued later.
+//!
+//! A checkpoint is little-endian binary: the magic bytes, the scene hash,
+//! the seed and integrator, the screen size, the region and number of
+//! passes, then each
+//! pixel's sums as [`f64`]s. The random numbers for a pass depend only on the
+//! seed and the pass number, so those are all the state needed to carry on
+//! exactly where the render stopped.
+
+use std::io::{self, Read, Write};
+
+use super::{Accumulator, Integrator, Region};
+
+const MAGIC: &[u8; 8] = b"RTCKPT2\n";
+
+/// The most pixels a checkpoint may hold, a 16384 by 16384 image, so that a
+/// corrupt region can't ask for an absurd amount of memory.
+const MAX_PIXELS: usize = 1 << 28;
+
+/// Everything needed to continue a progressive render.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    /// See [`scene_hash`].
+    pub scene_hash: u64,
+    pub seed: u64,
+    pub integrator: Integrator,
+    /// The resolution of the whole screen, which the same region of a
+    /// different resolution would show differently.
+    pub screen_width: u32,
+    pub screen_height: u32,
+    /// The part of the screen rendered, the same size as `accumulator`.
+    pub region: Region,
+    pub accumulator: Accumulator,
+}
+
+/// Identifies a scene by its text, to notice when a checkpoint was made from
+/// a different version of it. FNV-1a, which is plenty for telling edits
+/// apart.
+pub fn scene_hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl Checkpoint {
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let accumulator = &self.accumulator;
+        out.write_all(MAGIC)?;
+        out.write_all(&self.scene_hash.to_le_bytes())?;
+        out.write_all(&self.seed.to_le_bytes())?;
+        let integrator = self.integrator.to_string();
+        out.write_all(&[integrator.len() as u8])?;
+        out.write_all(integrator.as_bytes())?;
+        let Region { x0, y0, x1, y1 } = self.region;
+        assert_eq!(
+            (self.region.width(), self.region.height()),
+            (accumulator.width, accumulator.height),
+            "accumulator doesn't fit the region"
+        );
+        let (width, height) = (self.screen_width, self.screen_height);
+        for n in [width, height, x0, y0, x1, y1, accumulator.passes] {
+            out.write_all(&n.to_le_bytes())?;
+        }
+        for (sum, sum_squares) in accumulator.sum.iter().zip(&accumulator.sum_squares) {
+            for value in [sum[0], sum[1], sum[2], *sum_squares] {
+                out.write_all(&value.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails with [`io::ErrorKind::InvalidData`] if `input` isn't a
+    /// checkpoint.
+    pub fn read(input: &mut impl Read) -> io::Result<Self> {
+        let mut magic = [0; MAGIC.len()];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a checkpoint file"));
+        }
+        let scene_hash = u64::from_le_bytes(read_array(input)?);
+        let seed = u64::from_le_bytes(read_array(input)?);
+        let [len] = read_array(input)?;
+        let mut integrator = vec![0; len as usize];
+        input.read_exact(&mut integrator)?;
+        let integrator = String::from_utf8(integrator)
+            .ok()
+            .and_then(|name| name.parse().ok())
+            .ok_or_else(|| invalid("unknown integrator"))?;
+        let mut read_u32 = || read_array(input).map(u32::from_le_bytes);
+        let screen_width = read_u32()?;
+        let screen_height = read_u32()?;
+        let region = Region {
+            x0: read_u32()?,
+            y0: read_u32()?,
+            x1: read_u32()?,
+            y1: read_u32()?,
+        };
+        let passes = read_u32()?;
+        let Region { x0, y0, x1, y1 } = region;
+        if x0 >= x1 || y0 >= y1 {
+            return Err(invalid("empty region"));
+        }
+        let pixels = (region.width() as usize)
+            .checked_mul(region.height() as usize)
+            .filter(|&pixels| pixels <= MAX_PIXELS)
+            .ok_or_else(|| invalid("region too large"))?;
+        if !Region::whole(screen_width, screen_height).contains(&region) {
+            return Err(invalid("region outside the screen"));
+        }
+
+        let mut accumulator = Accumulator::new(0, 0);
+        accumulator.width = region.width();
+        accumulator.height = region.height();
+        accumulator.passes = passes;
+        // Grown as the pixels are read, so that a truncated file fails
+        // before it allocates the whole region.
+        for _ in 0..pixels {
+            let mut read_f64 = || read_array(input).map(f64::from_le_bytes);
+            let sum = [read_f64()?, read_f64()?, read_f64()?];
+            let sum_squares = read_f64()?;
+            accumulator.sum.push(sum);
+            accumulator.sum_squares.push(sum_squares);
+        }
+        if input.read(&mut [0])? != 0 {
+            return Err(invalid("trailing data after checkpoint"));
+        }
+        Ok(Self {
+            scene_hash,
+            seed,
+            integrator,
+            screen_width,
+            screen_height,
+            region,
+            accumulator,
+        })
+    }
+}
+
+fn read_array<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{RenderSettings, Scene, StopCondition};
+
+    #[test]
+    fn round_trip() {
+        let mut scene = Scene::from_sdl(include_str!("../../scene.sdl")).unwrap();
+        scene.set_resolution(16, 12);
+        let settings = RenderSettings {
+            seed: 7,
+            integrator: Integrator::Path,
+            ..RenderSettings::default()
+        };
+        let stop = |samples| StopCondition {
+            samples: Some(samples),
+            ..StopCondition::default()
+        };
+        let render = |accumulator: &mut Accumulator, samples| {
+            scene
+                .render_progressive(
+                    accumulator,
+                    &settings,
+                    &stop(samples),
+                    std::time::Duration::MAX,
+                    |_| Ok::<_, ()>(()),
+                )
+                .unwrap();
+        };
+
+        let mut accumulator = Accumulator::new(scene.width(), scene.height());
+        render(&mut accumulator, 2);
+        let checkpoint = Checkpoint {
+            scene_hash: scene_hash("scene"),
+            seed: settings.seed,
+            integrator: settings.integrator,
+            screen_width: 16,
+            screen_height: 12,
+            region: scene.region(),
+            accumulator,
+        };
+        let mut bytes = Vec::new();
+        checkpoint.write(&mut bytes).unwrap();
+        let mut resumed = Checkpoint::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(resumed, checkpoint);
+
+        // Continuing from the checkpoint gives the same result as never
+        // having stopped.
+        render(&mut resumed.accumulator, 3);
+        let mut uninterrupted = Accumulator::new(scene.width(), scene.height());
+        render(&mut uninterrupted, 3);
+        assert_eq!(resumed.accumulator, uninterrupted);
+
+        let err = Checkpoint::read(&mut &bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let err = Checkpoint::read(&mut &b"P6\n4 3\n255\n"[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_ne!(scene_hash("radius 1"), scene_hash("radius 2"));
+
+        let smaller = Checkpoint {
+            screen_width: 8,
+            ..checkpoint
+        };
+        let mut bytes = Vec::new();
+        smaller.write(&mut bytes).unwrap();
+        let err = Checkpoint::read(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(err.to_string(), "region outside the screen");
+    }
+
+    #[test]
+    fn huge_region() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend([0; 16]);
+        bytes.extend(b"\x04path");
+        // The screen and region "0 0 4294967295 4294967295", then the
+        // passes.
+        for n in [u32::MAX, u32::MAX, 0, 0, u32::MAX, u32::MAX, 1] {
+            bytes.extend(n.to_le_bytes());
+        }
+        let err = Checkpoint::read(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "region too large");
+    }
+}
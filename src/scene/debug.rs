@@ -0,0 +1,232 @@
+use crate::{image::Image, named::named_variants, vec3::Vec3};
+
+use super::{RenderSettings, Scene};
+
+/// Shading that shows how the renderer sees the scene, rather than what it
+/// looks like. Each pixel is shaded from the ray through its center.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DebugShading {
+    /// The surface normal's components, from -1..1 to 0..1, as red, green
+    /// and blue.
+    Normals,
+    /// Distance from the camera, as a heatmap from blue (nearest) to red
+    /// (farthest).
+    Depth,
+    /// A checkerboard in the surface's texture coordinates, with squares as
+    /// big as the focal distance. Magenta where an object has none.
+    UvChecker,
+    /// Each surface's color, without lighting.
+    Albedo,
+    /// White where a surface can see the light, black where it's in shadow.
+    Shadow,
+    /// How many ray-object intersection tests each pixel took, as a heatmap
+    /// up to the most on the screen.
+    Intersections,
+}
+
+named_variants! {
+    DebugShading, "debug shading" {
+        Normals => "normals",
+        Depth => "depth",
+        UvChecker => "uv",
+        Albedo => "albedo",
+        Shadow => "shadow",
+        Intersections => "intersections",
+    }
+}
+
+const MISSING_UV: [f32; 3] = [1., 0., 1.];
+
+impl Scene {
+    /// The pixel's color, or for heatmaps, the value to map in every
+    /// channel. See [`Self::finish_debug`].
+    pub(super) fn debug_pixel(&self, x: u32, y: u32, mode: DebugShading) -> [f32; 3] {
+        let ray = self.primary_ray(x as f64, y as f64);
+        let mut tests = 0;
+        let hit = self.closest_hit_counting(ray, f64::MAX, &mut tests);
+        let Some((index, p)) = hit else {
+            return match mode {
+                DebugShading::Depth => [f32::INFINITY; 3],
+                DebugShading::Intersections => [tests as f32; 3],
+                _ => [0.; 3],
+            };
+        };
+        let object = &self.objects[index];
+        let rgb = |v: Vec3| [v.x(), v.y(), v.z()].map(|c| c as f32);
+        match mode {
+            DebugShading::Normals => {
+                rgb(object.normal(p).normalize() * 0.5 + Vec3::new(0.5, 0.5, 0.5))
+            }
+            DebugShading::Depth => [(p - ray.start).norm() as f32; 3],
+            DebugShading::UvChecker => match object.uv(p) {
+                Some((u, v)) => {
+                    let size = self.focal_distance;
+                    let parity = ((u / size).floor() + (v / size).floor()).rem_euclid(2.);
+                    [if parity == 0. { 0.9 } else { 0.2 }; 3]
+                }
+                None => MISSING_UV,
+            },
+            DebugShading::Albedo => object.color().to_rgb(),
+            DebugShading::Shadow => {
+                let lit = !self.in_shadow(p, &mut tests);
+                [if lit { 1. } else { 0. }; 3]
+            }
+            DebugShading::Intersections => {
+                // As many as direct lighting does.
+                self.in_shadow(p, &mut tests);
+                [tests as f32; 3]
+            }
+        }
+    }
+
+    /// Turns heatmap values into colors, now that their range is known.
+    pub(super) fn finish_debug(&self, image: &mut Image, settings: &RenderSettings) {
+        let Some(mode @ (DebugShading::Depth | DebugShading::Intersections)) = settings.debug
+        else {
+            return;
+        };
+        // The range is over the whole screen, even if only part of it was
+        // rendered, so that a crop has the same colors as the full frame.
+        let values = if self.region == self.screen() {
+            image.pixels().iter().map(|p| p[0]).collect()
+        } else {
+            self.map_region(self.screen(), settings.threads, |x, y| {
+                self.debug_pixel(x, y, mode)[0]
+            })
+        };
+        let (min, max) = if mode == DebugShading::Depth {
+            let finite = values.iter().copied().filter(|d| d.is_finite());
+            let min = finite.clone().fold(f32::INFINITY, f32::min);
+            (min, finite.fold(min, f32::max))
+        } else {
+            (0., values.into_iter().fold(0., f32::max))
+        };
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                let [value, ..] = image.get(x, y);
+                let rgb = if value.is_finite() {
+                    let t = if max > min {
+                        (value - min) / (max - min)
+                    } else {
+                        0.
+                    };
+                    heatmap(t)
+                } else {
+                    [0.; 3]
+                };
+                image.set(x, y, rgb);
+            }
+        }
+    }
+}
+
+/// Blue, cyan, green, yellow, then red, as `t` goes from 0 to 1.
+fn heatmap(t: f32) -> [f32; 3] {
+    const STOPS: [[f32; 3]; 5] = [
+        [0., 0., 1.],
+        [0., 1., 1.],
+        [0., 1., 0.],
+        [1., 1., 0.],
+        [1., 0., 0.],
+    ];
+    let t = t.clamp(0., 1.) * (STOPS.len() - 1) as f32;
+    let i = (t.floor() as usize).min(STOPS.len() - 2);
+    let f = t - i as f32;
+    let (a, b) = (STOPS[i], STOPS[i + 1]);
+    [0, 1, 2].map(|c| a[c] + (b[c] - a[c]) * f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        color::Color,
+        objects::Plane,
+        scene::{tests::test_scene, Light},
+    };
+
+    fn scene() -> Scene {
+        // A sphere in front of a wall, lit from behind the wall's far side
+        // so that the sphere casts no shadow on it, and lit from in front
+        // so that its own back is in shadow.
+        test_scene()
+            .light(Light {
+                position: Vec3::new(0., 20., -20.),
+                color: Color::WHITE,
+                intensity: 1.,
+            })
+            .add(Plane {
+                anchor: Vec3::new(0., 0., 10.),
+                normal: Vec3::new(0., 0., -1.),
+                color: Color::BLUE,
+            })
+            .build()
+            .unwrap()
+    }
+
+    fn render(mode: DebugShading) -> Image {
+        scene().render_with(&RenderSettings {
+            debug: Some(mode),
+            ..RenderSettings::default()
+        })
+    }
+
+    #[test]
+    fn modes() {
+        // (7, 5) sees the front of the sphere; (0, 0) sees the wall.
+        let normals = render(DebugShading::Normals);
+        assert!(normals.get(7, 5)[2] < 0.1);
+        assert_eq!(normals.get(0, 0), [0.5, 0.5, 0.]);
+
+        let albedo = render(DebugShading::Albedo);
+        assert_eq!(albedo.get(7, 5), [0., 1., 0.]);
+        assert_eq!(albedo.get(0, 0), [0., 0., 1.]);
+
+        let depth = render(DebugShading::Depth);
+        assert_eq!(depth.get(7, 5), heatmap(0.));
+        assert_eq!(depth.get(0, 0), heatmap(1.));
+
+        let shadow = render(DebugShading::Shadow);
+        assert_eq!(shadow.get(7, 5), [1.; 3]);
+        assert_eq!(shadow.get(7, 11), [0.; 3]);
+
+        // Rays that reach the sphere or wall also test a shadow ray.
+        let intersections = render(DebugShading::Intersections);
+        assert_eq!(intersections.get(0, 0), heatmap(1.));
+
+        let checker = render(DebugShading::UvChecker);
+        let colors: Vec<_> = checker.pixels().iter().map(|p| p[0]).collect();
+        assert!(colors.contains(&0.9) && colors.contains(&0.2));
+    }
+
+    #[test]
+    fn cropped_heatmaps() {
+        for mode in [DebugShading::Depth, DebugShading::Intersections] {
+            let full = render(mode);
+            let mut scene = scene();
+            scene.set_region("6 4 9 7".parse().unwrap()).unwrap();
+            let crop = scene.render_with(&RenderSettings {
+                debug: Some(mode),
+                ..RenderSettings::default()
+            });
+            for (x, y) in [(0, 0), (1, 1), (2, 2)] {
+                assert_eq!(crop.get(x, y), full.get(x + 6, y + 4), "{mode}");
+            }
+        }
+    }
+
+    #[test]
+    fn heatmap_ends() {
+        assert_eq!(heatmap(0.), [0., 0., 1.]);
+        assert_eq!(heatmap(0.5), [0., 1., 0.]);
+        assert_eq!(heatmap(1.), [1., 0., 0.]);
+        assert_eq!(heatmap(7.), [1., 0., 0.]);
+    }
+
+    #[test]
+    fn names() {
+        for mode in DebugShading::ALL {
+            assert_eq!(mode.name().parse(), Ok(mode));
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::time::{Duration, Instant};
+
+use crate::{image::Image, rng::Rng};
+
+use super::{RenderSettings, Scene};
+
+/// Samples added up so far, pass by pass, for a progressive render.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Accumulator {
+    pub(super) width: u32,
+    pub(super) height: u32,
+    /// Every pixel has had this many samples.
+    pub(super) passes: u32,
+    /// Each pixel's samples, added up.
+    pub(super) sum: Vec<[f64; 3]>,
+    /// Each pixel's samples' luminance, squared and added up, for
+    /// estimating noise.
+    pub(super) sum_squares: Vec<f64>,
+}
+
+impl Accumulator {
+    pub fn new(width: u32, height: u32) -> Self {
+        let pixels = width as usize * height as usize;
+        Self {
+            width,
+            height,
+            passes: 0,
+            sum: vec![[0.; 3]; pixels],
+            sum_squares: vec![0.; pixels],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The number of samples per pixel so far.
+    pub fn passes(&self) -> u32 {
+        self.passes
+    }
+
+    /// The mean of each pixel's samples.
+    pub fn image(&self) -> Image {
+        let mut image = Image::new(self.width, self.height);
+        let n = self.passes.max(1) as f64;
+        for (i, sum) in self.sum.iter().enumerate() {
+            let i = i as u32;
+            image.set(i % self.width, i / self.width, sum.map(|c| (c / n) as f32));
+        }
+        image
+    }
+
+    /// The standard error of the pixels' mean luminance, root-mean-squared
+    /// over the image: roughly how far pixels are from their converged
+    /// values, on the scale where white is 1. Infinite until there are two
+    /// passes.
+    pub fn noise(&self) -> f64 {
+        if self.passes < 2 {
+            return f64::INFINITY;
+        }
+        let n = self.passes as f64;
+        let total: f64 = self
+            .sum
+            .iter()
+            .zip(&self.sum_squares)
+            .map(|(&sum, &sum_squares)| {
+                let mean = luminance(sum) / n;
+                let variance = (sum_squares / n - mean * mean).max(0.) * n / (n - 1.);
+                variance / n
+            })
+            .sum();
+        (total / self.sum.len().max(1) as f64).sqrt()
+    }
+}
+
+fn luminance([r, g, b]: [f64; 3]) -> f64 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// When a progressive render stops: at whichever limit comes first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StopCondition {
+    /// Samples per pixel.
+    pub samples: Option<u32>,
+    pub time: Option<Duration>,
+    /// See [`Accumulator::noise`].
+    pub noise: Option<f64>,
+}
+
+impl StopCondition {
+    /// Whether there's any limit at all.
+    pub fn is_bounded(&self) -> bool {
+        self.samples.is_some() || self.time.is_some() || self.noise.is_some()
+    }
+
+    fn is_met(&self, accumulator: &Accumulator, elapsed: Duration) -> bool {
+        self.samples.is_some_and(|n| accumulator.passes >= n)
+            || self.time.is_some_and(|limit| elapsed >= limit)
+            || self.noise.is_some_and(|limit| accumulator.noise() <= limit)
+    }
+}
+
+impl Scene {
+    /// Adds one sample to every pixel. `settings.samples` and
+    /// `settings.debug` are ignored.
+    pub fn render_pass(&self, accumulator: &mut Accumulator, settings: &RenderSettings) {
+        assert_eq!(
+            (accumulator.width, accumulator.height),
+            (self.width(), self.height()),
+            "accumulator is for a different resolution"
+        );
+        // A different stream per pass and pixel, so that passes don't
+        // repeat each other, and don't depend on threads.
+        let seed = Rng::stream(settings.seed, accumulator.passes as u64).next_u64();
+        let samples = self.map_pixels(settings.threads, |x, y| {
+            let pixel = y as u64 * self.screen_width as u64 + x as u64;
+            let mut rng = Rng::stream(seed, pixel);
+            self.sample(x, y, true, settings.integrator, &mut rng)
+        });
+        for (i, sample) in samples.into_iter().enumerate() {
+            let rgb = [sample.x(), sample.y(), sample.z()];
+            let sum = &mut accumulator.sum[i];
+            for c in 0..3 {
+                sum[c] += rgb[c];
+            }
+            accumulator.sum_squares[i] += luminance(rgb).powi(2);
+        }
+        accumulator.passes += 1;
+    }
+
+    /// Renders pass after pass into `accumulator` until `stop` is met,
+    /// calling `refresh` at most every `interval`, and after the last pass.
+    /// The accumulator may already hold passes, e.g. to continue a render.
+    ///
+    /// # Panics
+    ///
+    /// If `stop` has no limits.
+    pub fn render_progressive<E>(
+        &self,
+        accumulator: &mut Accumulator,
+        settings: &RenderSettings,
+        stop: &StopCondition,
+        interval: Duration,
+        mut refresh: impl FnMut(&Accumulator) -> Result<(), E>,
+    ) -> Result<(), E> {
+        assert!(stop.is_bounded(), "progressive render would never stop");
+        let start = Instant::now();
+        let mut last_refresh = start;
+        let mut done = stop.is_met(accumulator, start.elapsed());
+        while !done {
+            self.render_pass(accumulator, settings);
+            done = stop.is_met(accumulator, start.elapsed());
+            if !done && last_refresh.elapsed() >= interval {
+                refresh(accumulator)?;
+                last_refresh = Instant::now();
+            }
+        }
+        refresh(accumulator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{tests::test_scene, Integrator};
+
+    #[test]
+    fn stops_on_samples() {
+        let scene = test_scene().screen(8, 6).build().unwrap();
+        let mut accumulator = Accumulator::new(8, 6);
+        let mut refreshes = Vec::new();
+        let stop = StopCondition {
+            samples: Some(5),
+            ..StopCondition::default()
+        };
+        scene
+            .render_progressive(
+                &mut accumulator,
+                &RenderSettings::default(),
+                &stop,
+                Duration::ZERO,
+                |acc| {
+                    refreshes.push(acc.passes());
+                    Ok::<_, ()>(())
+                },
+            )
+            .unwrap();
+        assert_eq!(refreshes, [1, 2, 3, 4, 5]);
+        assert_eq!(accumulator.passes(), 5);
+
+        // Continuing adds more passes, rather than starting again.
+        let stop = StopCondition {
+            samples: Some(7),
+            ..stop
+        };
+        scene
+            .render_progressive(
+                &mut accumulator,
+                &RenderSettings::default(),
+                &stop,
+                Duration::MAX,
+                |_| Ok::<_, ()>(()),
+            )
+            .unwrap();
+        assert_eq!(accumulator.passes(), 7);
+    }
+
+    #[test]
+    fn stops_on_noise_or_time() {
+        let scene = test_scene().screen(8, 6).build().unwrap();
+        let settings = RenderSettings {
+            integrator: Integrator::Path,
+            ..RenderSettings::default()
+        };
+        let mut accumulator = Accumulator::new(8, 6);
+        assert_eq!(accumulator.noise(), f64::INFINITY);
+        let stop = StopCondition {
+            noise: Some(0.02),
+            ..StopCondition::default()
+        };
+        let never = Duration::MAX;
+        let ok = |_: &Accumulator| Ok::<_, ()>(());
+        scene
+            .render_progressive(&mut accumulator, &settings, &stop, never, ok)
+            .unwrap();
+        assert!(accumulator.noise() <= 0.02);
+        assert!(accumulator.passes() >= 2);
+
+        let mut accumulator = Accumulator::new(8, 6);
+        let stop = StopCondition {
+            time: Some(Duration::ZERO),
+            ..StopCondition::default()
+        };
+        scene
+            .render_progressive(&mut accumulator, &settings, &stop, never, ok)
+            .unwrap();
+        assert_eq!(accumulator.passes(), 0);
+    }
+
+    #[test]
+    fn converges() {
+        // With the direct integrator, samples only vary with where they fall
+        // in the pixel, so the mean is close to the pixel center's color.
+        let scene = test_scene().screen(8, 6).build().unwrap();
+        let mut accumulator = Accumulator::new(8, 6);
+        for _ in 0..64 {
+            scene.render_pass(&mut accumulator, &RenderSettings::default());
+        }
+        let image = accumulator.image();
+        let reference = scene.render();
+        for (a, b) in image.get(4, 3).into_iter().zip(reference.get(4, 3)) {
+            assert!((a - b).abs() < 0.02, "{a} {b}");
+        }
+        assert_eq!(image.get(0, 0), [0.; 3]);
+        assert!(accumulator.noise() < 0.05, "{}", accumulator.noise());
+    }
+}
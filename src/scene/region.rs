@@ -0,0 +1,113 @@
+use std::{fmt, str::FromStr};
+
+/// A rectangle of pixels, from `(x0, y0)` at the top left up to but not
+/// including `(x1, y1)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl Region {
+    /// All of a `width` by `height` image.
+    pub fn whole(width: u32, height: u32) -> Self {
+        Self {
+            x0: 0,
+            y0: 0,
+            x1: width,
+            y1: height,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+
+    pub fn contains(&self, other: &Region) -> bool {
+        self.x0 <= other.x0 && other.x1 <= self.x1 && self.y0 <= other.y0 && other.y1 <= self.y1
+    }
+
+    /// Splits the region into squares of `size` pixels, row by row from the
+    /// top left. Those on the right and bottom edges are cut short.
+    pub fn tiles(&self, size: u32) -> Vec<Region> {
+        assert!(size > 0, "empty tiles");
+        let mut tiles = Vec::new();
+        for y0 in (self.y0..self.y1).step_by(size as usize) {
+            for x0 in (self.x0..self.x1).step_by(size as usize) {
+                tiles.push(Region {
+                    x0,
+                    y0,
+                    x1: (x0 + size).min(self.x1),
+                    y1: (y0 + size).min(self.y1),
+                });
+            }
+        }
+        tiles
+    }
+}
+
+/// `x0 y0 x1 y1`, separated by spaces or commas.
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || "expected x0 y0 x1 y1, with x0 < x1 and y0 < y1".to_owned();
+        let coords: Vec<u32> = s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|coord| !coord.is_empty())
+            .map(|coord| coord.parse().map_err(|_| invalid()))
+            .collect::<Result<_, _>>()?;
+        let [x0, y0, x1, y1] = coords[..] else {
+            return Err(invalid());
+        };
+        if x0 >= x1 || y0 >= y1 {
+            return Err(invalid());
+        }
+        Ok(Self { x0, y0, x1, y1 })
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { x0, y0, x1, y1 } = self;
+        write!(f, "{x0} {y0} {x1} {y1}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let region = Region {
+            x0: 1,
+            y0: 2,
+            x1: 30,
+            y1: 40,
+        };
+        assert_eq!("1 2 30 40".parse(), Ok(region));
+        assert_eq!("1,2,30,40".parse(), Ok(region));
+        assert_eq!(region.to_string().parse(), Ok(region));
+        assert!("1 2 30".parse::<Region>().is_err());
+        assert!("30 2 1 40".parse::<Region>().is_err());
+        assert!("1 2 -30 40".parse::<Region>().is_err());
+    }
+
+    #[test]
+    fn tiles() {
+        let tiles = Region::whole(10, 5).tiles(4);
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(tiles[2], "8 0 10 4".parse().unwrap());
+        assert_eq!(tiles[5], "8 4 10 5".parse().unwrap());
+        let area: u32 = tiles.iter().map(|t| t.width() * t.height()).sum();
+        assert_eq!(area, 50);
+        assert!(tiles.iter().all(|t| Region::whole(10, 5).contains(t)));
+    }
+}
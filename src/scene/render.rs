@@ -0,0 +1,341 @@
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+};
+
+use crate::{color::Color, image::Image, named::named_variants, rng::Rng, vec3::Vec3};
+
+use super::{DebugShading, Ray, Region, Scene};
+
+/// How the color seen along a ray is computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Integrator {
+    /// Light reaching a surface straight from the light source, with hard
+    /// shadows.
+    #[default]
+    Direct,
+    /// Direct light, plus light bounced diffusely between surfaces. Noisy
+    /// unless there are many samples per pixel.
+    Path,
+}
+
+named_variants! {
+    Integrator, "integrator" {
+        Direct => "direct",
+        Path => "path",
+    }
+}
+
+/// Options for [`Scene::render_with`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderSettings {
+    /// Samples per pixel, at least 1. A single sample goes through the
+    /// center of the pixel; more are spread randomly across it.
+    pub samples: u32,
+    /// At least 1. The image doesn't depend on the number of threads.
+    pub threads: usize,
+    pub seed: u64,
+    pub integrator: Integrator,
+    /// Overrides the integrator, with one sample per pixel.
+    pub debug: Option<DebugShading>,
+}
+
+impl RenderSettings {
+    /// Whether each pixel is lit directly from the one ray through its
+    /// center, so that what the ray hit can be shared, e.g. with the AOVs.
+    pub(super) fn center_only(&self) -> bool {
+        self.debug.is_none() && self.samples.max(1) == 1 && self.integrator == Integrator::Direct
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            samples: 1,
+            threads: 1,
+            seed: 0,
+            integrator: Integrator::Direct,
+            debug: None,
+        }
+    }
+}
+
+/// How many times a path may bounce before it's cut off.
+const MAX_BOUNCES: u32 = 4;
+
+impl Scene {
+    pub fn render_with(&self, settings: &RenderSettings) -> Image {
+        let pixels = self.map_pixels(settings.threads, |x, y| self.render_pixel(x, y, settings));
+        let width = self.width();
+        let mut image = Image::new(width, self.height());
+        for (i, rgb) in pixels.into_iter().enumerate() {
+            let i = i as u32;
+            image.set(i % width, i / width, rgb);
+        }
+        self.finish_debug(&mut image, settings);
+        image
+    }
+
+    /// Calls `f` for every pixel in the region, spread over `threads`
+    /// threads, and collects the results row by row from the top left.
+    /// Pixels are numbered as on the whole screen, so that they come out the
+    /// same whatever the region.
+    pub(super) fn map_pixels<T: Send>(
+        &self,
+        threads: usize,
+        f: impl Fn(u32, u32) -> T + Sync,
+    ) -> Vec<T> {
+        self.map_region(self.region, threads, f)
+    }
+
+    /// [`Self::map_pixels`] over some other part of the screen.
+    pub(super) fn map_region<T: Send>(
+        &self,
+        region: Region,
+        threads: usize,
+        f: impl Fn(u32, u32) -> T + Sync,
+    ) -> Vec<T> {
+        let Region { x0, y0, x1, y1 } = region;
+        let next_row = AtomicU32::new(y0);
+        let render_rows = || {
+            let mut rows = Vec::new();
+            loop {
+                let y = next_row.fetch_add(1, Ordering::Relaxed);
+                if y >= y1 {
+                    return rows;
+                }
+                let row: Vec<_> = (x0..x1).map(|x| f(x, y)).collect();
+                rows.push((y, row));
+            }
+        };
+
+        let mut rows: Vec<(u32, Vec<T>)> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads.max(1))
+                .map(|_| scope.spawn(render_rows))
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("render thread panicked"))
+                .collect()
+        });
+        rows.sort_by_key(|&(y, _)| y);
+        rows.into_iter().flat_map(|(_, row)| row).collect()
+    }
+
+    pub(super) fn render_pixel(&self, x: u32, y: u32, settings: &RenderSettings) -> [f32; 3] {
+        if let Some(mode) = settings.debug {
+            return self.debug_pixel(x, y, mode);
+        }
+        if settings.center_only() {
+            let (_, hit) = self.center_hit(x, y);
+            return to_rgb(self.shade(hit));
+        }
+        let samples = settings.samples.max(1);
+
+        // Each pixel gets its own stream, so that the result doesn't depend
+        // on which thread renders it.
+        let pixel = y as u64 * self.screen_width as u64 + x as u64;
+        let mut rng = Rng::stream(settings.seed, pixel);
+        let mut sum = Vec3::ZERO;
+        for _ in 0..samples {
+            sum = sum + self.sample(x, y, samples > 1, settings.integrator, &mut rng);
+        }
+        to_rgb(sum / samples as f64)
+    }
+
+    /// The ray through the center of a pixel, and what it hits first.
+    pub(super) fn center_hit(&self, x: u32, y: u32) -> (Ray, Option<(usize, Vec3)>) {
+        let ray = self.primary_ray(x as f64, y as f64);
+        (ray, self.closest_hit(ray, f64::MAX))
+    }
+
+    /// The color seen through a pixel: through its center, or with
+    /// `jitter`, through a random point in it.
+    pub(super) fn sample(
+        &self,
+        x: u32,
+        y: u32,
+        jitter: bool,
+        integrator: Integrator,
+        rng: &mut Rng,
+    ) -> Vec3 {
+        let (dx, dy) = if jitter {
+            (rng.next_f64() - 0.5, rng.next_f64() - 0.5)
+        } else {
+            (0., 0.)
+        };
+        let ray = self.primary_ray(x as f64 + dx, y as f64 + dy);
+        match integrator {
+            Integrator::Direct => self.direct(ray),
+            Integrator::Path => self.trace_path(ray, rng),
+        }
+    }
+
+    /// Follows `ray` through diffuse bounces, adding up the direct light seen
+    /// at each surface.
+    fn trace_path(&self, mut ray: Ray, rng: &mut Rng) -> Vec3 {
+        let mut radiance = Vec3::ZERO;
+        let mut throughput = Vec3::new(1., 1., 1.);
+        for _ in 0..MAX_BOUNCES {
+            let Some((index, p)) = self.closest_hit(ray, f64::MAX) else {
+                break;
+            };
+            radiance = radiance + throughput.direct_product(self.shade(Some((index, p))));
+            let obj = &self.objects[index];
+
+            // Bounce off the side of the surface that the ray came from.
+            let mut normal = obj.normal(p);
+            if normal * ray.direction > 0. {
+                normal = -normal;
+            }
+            let direction = cosine_weighted(normal, rng);
+            throughput = throughput.direct_product(to_vec(obj.color()));
+            ray = Ray {
+                // The same offset as for shadow rays, to avoid hitting the
+                // surface we're leaving.
+                start: p + direction * 0.1,
+                direction,
+            };
+        }
+        radiance
+    }
+}
+
+/// A random direction in the hemisphere around `normal`, more likely near
+/// the normal in proportion to the cosine of the angle from it. This cancels
+/// out the cosine in a diffuse surface's reflectance.
+fn cosine_weighted(normal: Vec3, rng: &mut Rng) -> Vec3 {
+    let helper = if normal.x().abs() > 0.9 {
+        Vec3::Y_AXIS
+    } else {
+        Vec3::X_AXIS
+    };
+    let u = helper.cross_product(normal).normalize();
+    let v = normal.cross_product(u);
+
+    let r = rng.next_f64().sqrt();
+    let phi = 2. * std::f64::consts::PI * rng.next_f64();
+    let z = (1. - r * r).max(0.).sqrt();
+    u * (r * phi.cos()) + v * (r * phi.sin()) + normal * z
+}
+
+pub(super) fn to_vec(color: Color) -> Vec3 {
+    let [r, g, b] = color.to_rgb();
+    Vec3::new(r as f64, g as f64, b as f64)
+}
+
+/// For an [`Image`], which keeps values above 1 until it's written out.
+pub(super) fn to_rgb(v: Vec3) -> [f32; 3] {
+    [v.x(), v.y(), v.z()].map(|c| c as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::tests::test_scene;
+
+    #[test]
+    fn threads_and_seeds() {
+        let scene = test_scene().build().unwrap();
+        let settings = RenderSettings {
+            samples: 4,
+            integrator: Integrator::Path,
+            ..RenderSettings::default()
+        };
+        let one = scene.render_with(&settings);
+        let four = scene.render_with(&RenderSettings {
+            threads: 4,
+            ..settings.clone()
+        });
+        assert_eq!(one, four);
+
+        let reseeded = scene.render_with(&RenderSettings {
+            seed: 1,
+            ..settings
+        });
+        assert_ne!(one, reseeded);
+
+        assert_eq!(
+            scene.render(),
+            scene.render_with(&RenderSettings {
+                threads: 3,
+                ..RenderSettings::default()
+            })
+        );
+    }
+
+    #[test]
+    fn resolution() {
+        let mut scene = test_scene().build().unwrap();
+        let full = scene.render();
+        scene.set_resolution(8, 6);
+        let half = scene.render();
+        assert_eq!((half.width(), half.height()), (8, 6));
+
+        // The sphere covers the middle of the image at either resolution;
+        // the corners are empty.
+        for image in [&full, &half] {
+            let (w, h) = (image.width(), image.height());
+            assert_ne!(image.get(w / 2, h / 2), [0.; 3]);
+            assert_eq!(image.get(0, 0), [0.; 3]);
+        }
+    }
+
+    #[test]
+    fn region() {
+        let mut scene = test_scene().build().unwrap();
+        let settings = RenderSettings {
+            samples: 2,
+            integrator: Integrator::Path,
+            ..RenderSettings::default()
+        };
+        let full = scene.render_with(&settings);
+        let region: Region = "3 2 11 7".parse().unwrap();
+        scene.set_region(region).unwrap();
+        let crop = scene.render_with(&settings);
+        assert_eq!((crop.width(), crop.height()), (8, 5));
+        for y in 0..crop.height() {
+            for x in 0..crop.width() {
+                assert_eq!(crop.get(x, y), full.get(x + 3, y + 2));
+            }
+        }
+
+        let outside = "3 2 17 7".parse().unwrap();
+        assert_eq!(
+            scene.set_region(outside).unwrap_err(),
+            "region 3 2 17 7 is outside the 16x12 screen"
+        );
+        scene.set_resolution(8, 6);
+        assert_eq!(scene.region(), Region::whole(8, 6));
+    }
+
+    #[test]
+    fn high_dynamic_range() {
+        let dim = test_scene().build().unwrap();
+        let mut bright = test_scene().build().unwrap();
+        bright.light.intensity = 4.;
+        for settings in [
+            RenderSettings::default(),
+            RenderSettings {
+                samples: 2,
+                integrator: Integrator::Path,
+                ..RenderSettings::default()
+            },
+        ] {
+            let [_, g, _] = dim.render_with(&settings).get(8, 6);
+            let [_, bright_g, _] = bright.render_with(&settings).get(8, 6);
+            assert!(g > 0.2, "{g}");
+            assert!(bright_g > 1., "{bright_g}");
+            assert!((bright_g - 4. * g).abs() < 1e-4, "{g} {bright_g}");
+        }
+    }
+
+    #[test]
+    fn integrator_names() {
+        for name in Integrator::ALL.map(Integrator::name) {
+            let integrator: Integrator = name.parse().unwrap();
+            assert_eq!(integrator.to_string(), name);
+        }
+        assert!("whitted".parse::<Integrator>().is_err());
+    }
+}
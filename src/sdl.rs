@@ -1,30 +1,198 @@
 //! Scene description language.
 
+mod expand;
+mod expr;
+mod from_node;
+mod include;
 mod into_scene;
+mod json;
 mod parse;
+mod print;
+mod registry;
+mod schema;
 
-use crate::error::Result;
+use std::{collections::HashMap, error::Error as StdError, fmt, rc::Rc};
 
-#[derive(Debug)]
+pub use expand::expand;
+pub use from_node::{check, FromNode};
+pub use include::{parse_file, resolve_includes};
+pub use into_scene::SceneFile;
+pub use json::parse_json_file;
+pub use parse::{ParseError, ParseErrorKind};
+pub use print::format;
+pub use registry::register_object;
+pub use schema::{validate, Field, FieldType, Presence, Schema, ValidationError};
+
+#[derive(Debug, Clone)]
 pub struct Node {
     name: String,
-    values: Vec<f64>,
+    values: Vec<Value>,
     children: Vec<Node>,
+    /// Comment lines directly above the node in the source, including the
+    /// `//`. An empty entry stands for a blank line.
+    comments: Vec<String>,
+    span: Span,
+    /// Human-readable location in the tree, e.g. `scene > objects > sphere[1]`.
+    ///
+    /// Filled in once the whole tree has been parsed.
+    path: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    /// Quoted in the source, e.g. `"teapot.obj"`.
+    String(String),
+    /// A bare word, e.g. `orthographic`.
+    Ident(String),
+    /// `true`/`on` or `false`/`off`.
+    Bool(bool),
+}
+
+impl Value {
+    /// Describes the kind of value, for error messages.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Number(_) => "number",
+            Self::String(_) => "string",
+            Self::Ident(_) => "identifier",
+            Self::Bool(_) => "boolean",
+        }
+    }
+}
+
+/// Formats the value as it would be written in SDL.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Number(n) => write!(f, "{n}"),
+            Self::Ident(s) => write!(f, "{s}"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::String(s) => {
+                f.write_str("\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        '\n' => f.write_str("\\n")?,
+                        '\t' => f.write_str("\\t")?,
+                        c => write!(f, "{c}")?,
+                    }
+                }
+                f.write_str("\"")
+            }
+        }
+    }
+}
+
+/// Where a node's name appears in the source. Line and column are 1-based.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Span {
+    /// Only set for nodes read from a file, e.g. by `include`.
+    pub file: Option<Rc<str>>,
+    pub line: usize,
+    pub column: usize,
 }
 
 impl Node {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    pub fn children(&self) -> &[Node] {
+        &self.children
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// E.g. `scene > objects > sphere[1]`.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The number of nodes in the tree rooted here, including this one.
+    pub(super) fn size(&self) -> usize {
+        1 + self.children.iter().map(Node::size).sum::<usize>()
+    }
+
     /// `path` is a whitespace-separated string of names.
     ///
     /// Each name must describe exactly one node, or this will fail.
-    pub fn get_path(&self, path: &str) -> Result<&Node> {
+    pub fn get_path(&self, path: &str) -> Result<&Node, NodeError> {
         let mut curr = self;
         for name in path.split_whitespace() {
             curr = curr
                 .children
                 .iter()
                 .find(|ch| ch.name == name)
-                .ok_or_else(|| format!("no child named {name:?}"))?;
+                .ok_or_else(|| curr.error(format!("no child named {name:?}")))?;
         }
         Ok(curr)
     }
+
+    /// An error about this node, tagged with its path and source location.
+    pub fn error(&self, message: impl Into<String>) -> NodeError {
+        NodeError {
+            path: self.path.clone(),
+            span: self.span.clone(),
+            message: message.into(),
+        }
+    }
+
+    /// Sets `path` for this node and all its descendants.
+    ///
+    /// Siblings that share a name get an index, e.g. `sphere[0]`, `sphere[1]`.
+    fn assign_paths(&mut self, path: String) {
+        // How many children have each name, then how many of those have been
+        // seen so far.
+        let mut totals: HashMap<String, usize> = HashMap::new();
+        for child in &self.children {
+            *totals.entry(child.name.clone()).or_default() += 1;
+        }
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        for child in &mut self.children {
+            let name = &child.name;
+            let segment = if totals[name] > 1 {
+                let index = seen.entry(name.clone()).or_default();
+                *index += 1;
+                format!("{name}[{}]", *index - 1)
+            } else {
+                name.clone()
+            };
+            child.assign_paths(format!("{path} > {segment}"));
+        }
+        self.path = path;
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}", self.line)?;
+        if let Some(file) = &self.file {
+            write!(f, " of {file}")?;
+        }
+        Ok(())
+    }
 }
+
+/// A semantic error in an SDL tree, e.g. a missing or malformed field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeError {
+    pub path: String,
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for NodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.path, self.span, self.message)
+    }
+}
+
+impl StdError for NodeError {}
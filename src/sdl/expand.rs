@@ -0,0 +1,437 @@
+//! Rewrites a parsed tree to resolve references between its parts, so that
+//! validation and conversion only ever see plain nodes.
+//!
+//! - `define NAME { ... }` blocks at the top level are templates. A `use NAME`
+//!   line anywhere is replaced by the template's contents.
+//! - The top-level `materials { NAME { ... } }` block declares materials. An
+//!   object's `material NAME` line is replaced by the material's properties,
+//!   except those the object sets itself. A material may build on another
+//!   with its own `material` line.
+
+use std::collections::HashMap;
+use std::mem;
+
+use super::{
+    parse::{is_valid_name, MAX_GENERATED_NODES},
+    schema::with_suggestion,
+    Node, NodeError, Value,
+};
+
+pub fn expand(tree: &mut Node) -> Result<(), NodeError> {
+    let defines = take_defines(tree)?;
+    let mut budget = MAX_GENERATED_NODES.saturating_sub(tree.size());
+    expand_uses(tree, &defines, &mut vec![], &mut budget)?;
+
+    let materials = take_materials(tree)?;
+    apply_materials(tree, &materials)?;
+
+    tree.assign_paths(tree.name.clone());
+    Ok(())
+}
+
+/// Definitions by name.
+type Definitions = HashMap<String, Node>;
+
+/// Removes the `define` blocks from the top level of the tree.
+fn take_defines(tree: &mut Node) -> Result<Definitions, NodeError> {
+    let mut defines = Definitions::new();
+    let (taken, rest) = mem::take(&mut tree.children)
+        .into_iter()
+        .partition(|ch| ch.name == "define");
+    tree.children = rest;
+
+    for define in taken {
+        let name = ident_value(&define)?.to_owned();
+        insert_unique(&mut defines, name, define)?;
+    }
+    Ok(defines)
+}
+
+/// Removes the top-level `materials` block, if there is one.
+fn take_materials(tree: &mut Node) -> Result<Definitions, NodeError> {
+    let mut materials = Definitions::new();
+    let Some(idx) = tree.children.iter().position(|ch| ch.name == "materials") else {
+        return Ok(materials);
+    };
+    let block = tree.children.remove(idx);
+    if let Some(extra) = tree.children.iter().find(|ch| ch.name == "materials") {
+        return Err(extra.error(format!(
+            "duplicate key \"materials\" (first defined on {})",
+            block.span
+        )));
+    }
+    block.expect_block("materials")?;
+
+    for material in block.children {
+        material.expect_block("material")?;
+        insert_unique(&mut materials, material.name.clone(), material)?;
+    }
+    Ok(materials)
+}
+
+fn insert_unique(defs: &mut Definitions, name: String, node: Node) -> Result<(), NodeError> {
+    if let Some(first) = defs.get(&name) {
+        return Err(node.error(format!("{name:?} is already defined on {}", first.span)));
+    }
+    defs.insert(name, node);
+    Ok(())
+}
+
+/// The single identifier in a `define`, `use` or `material` line. A string
+/// will do if it's a valid name, since that's all JSON can give.
+fn ident_value(node: &Node) -> Result<&str, NodeError> {
+    match node.values.as_slice() {
+        [Value::Ident(name)] => Ok(name),
+        [Value::String(name)] if is_valid_name(name) => Ok(name),
+        [other] => Err(node.mismatch(&node.name, "a name", other)),
+        values => Err(node.error(format!("expected a name, found {} values", values.len()))),
+    }
+}
+
+/// Looks up a reference, checking that it's defined and not circular.
+///
+/// `stack` holds the names currently being expanded.
+fn resolve<'a>(
+    reference: &Node,
+    defs: &'a Definitions,
+    stack: &[String],
+) -> Result<(&'a str, &'a Node), NodeError> {
+    let name = ident_value(reference)?;
+    if stack.iter().any(|s| s == name) {
+        let cycle: Vec<_> = stack.iter().map(String::as_str).chain([name]).collect();
+        return Err(reference.error(format!("circular reference: {}", cycle.join(" -> "))));
+    }
+    match defs.get_key_value(name) {
+        Some((name, def)) => Ok((name, def)),
+        None => {
+            let message = format!("undefined {} {name:?}", kind(reference));
+            let known = defs.keys().map(String::as_str);
+            Err(reference.error(with_suggestion(message, name, known)))
+        }
+    }
+}
+
+fn kind(reference: &Node) -> &'static str {
+    if reference.name == "material" {
+        "material"
+    } else {
+        "definition"
+    }
+}
+
+/// Replaces each `use NAME` line with the contents of that definition.
+/// `budget` is how many more nodes the copies may add, checked before each
+/// copy so that nested uses can't multiply out of hand.
+fn expand_uses(
+    node: &mut Node,
+    defines: &Definitions,
+    stack: &mut Vec<String>,
+    budget: &mut usize,
+) -> Result<(), NodeError> {
+    for mut child in mem::take(&mut node.children) {
+        if child.name != "use" {
+            expand_uses(&mut child, defines, stack, budget)?;
+            node.children.push(child);
+            continue;
+        }
+
+        if !child.children.is_empty() {
+            return Err(child.error("`use` can't have a block"));
+        }
+        let (name, define) = resolve(&child, defines, stack)?;
+        let size = define.size() - 1;
+        if size > *budget {
+            return Err(child.error(format!(
+                "too many nodes generated by `use {name}`, more than {MAX_GENERATED_NODES}"
+            )));
+        }
+        *budget -= size;
+        let mut body = define.clone();
+        stack.push(name.to_owned());
+        expand_uses(&mut body, defines, stack, budget)?;
+        stack.pop();
+        node.children.extend(body.children);
+    }
+    Ok(())
+}
+
+/// Replaces each `material NAME` line with the properties of that material.
+fn apply_materials(node: &mut Node, materials: &Definitions) -> Result<(), NodeError> {
+    for child in &mut node.children {
+        apply_materials(child, materials)?;
+    }
+
+    let Some(idx) = node.children.iter().position(|ch| ch.name == "material") else {
+        return Ok(());
+    };
+    let reference = node.children.remove(idx);
+    if let Some(extra) = node.children.iter().find(|ch| ch.name == "material") {
+        return Err(extra.error(format!(
+            "duplicate key \"material\" (first defined on {})",
+            reference.span
+        )));
+    }
+
+    for property in material_properties(&reference, materials, &mut vec![])? {
+        if !node.children.iter().any(|ch| ch.name == property.name) {
+            node.children.push(property);
+        }
+    }
+    Ok(())
+}
+
+/// All the properties of a material, including those it inherits.
+fn material_properties(
+    reference: &Node,
+    materials: &Definitions,
+    stack: &mut Vec<String>,
+) -> Result<Vec<Node>, NodeError> {
+    let (name, material) = resolve(reference, materials, stack)?;
+
+    let mut properties = Vec::new();
+    let mut inherited = Vec::new();
+    for child in &material.children {
+        if child.name == "material" {
+            stack.push(name.to_owned());
+            inherited.extend(material_properties(child, materials, stack)?);
+            stack.pop();
+        } else {
+            properties.push(child.clone());
+        }
+    }
+
+    // A material's own properties override those it inherits.
+    for property in inherited {
+        if !properties.iter().any(|p| p.name == property.name) {
+            properties.push(property);
+        }
+    }
+    Ok(properties)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expanded(text: &str) -> Result<Node, NodeError> {
+        let mut tree: Node = text.parse().unwrap();
+        expand(&mut tree)?;
+        Ok(tree)
+    }
+
+    fn numbers(node: &Node, path: &str) -> Vec<f64> {
+        let values = &node.get_path(path).unwrap().values;
+        values
+            .iter()
+            .map(|v| match v {
+                Value::Number(x) => *x,
+                _ => panic!("not a number: {v}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn define_and_use() {
+        let tree = expanded(
+            "scene {
+    define wall {
+        color 255 255 255
+        normal 1 0 0
+    }
+    objects {
+        plane {
+            use wall
+            anchor 30 0 0
+        }
+    }
+}",
+        )
+        .unwrap();
+        assert!(tree.get_path("define").is_err());
+        let plane = tree.get_path("objects plane").unwrap();
+        assert_eq!(numbers(plane, "color"), [255., 255., 255.]);
+        assert_eq!(numbers(plane, "normal"), [1., 0., 0.]);
+        assert_eq!(numbers(plane, "anchor"), [30., 0., 0.]);
+        assert_eq!(
+            plane.get_path("color").unwrap().path,
+            "scene > objects > plane > color"
+        );
+    }
+
+    #[test]
+    fn too_many_nodes() {
+        // Each definition uses the one before twice, for 2^24 nodes in all.
+        let mut text = "scene {\n    define d0 {\n        x\n    }\n".to_owned();
+        for n in 1..=24 {
+            let prev = n - 1;
+            text +=
+                &format!("    define d{n} {{\n        use d{prev}\n        use d{prev}\n    }}\n");
+        }
+        text += "    use d24\n}";
+        let e = expanded(&text).unwrap_err();
+        assert!(
+            e.message.starts_with("too many nodes generated by `use d"),
+            "{e}"
+        );
+    }
+
+    #[test]
+    fn nested_uses() {
+        let tree = expanded(
+            "scene {
+    define white {
+        color 255 255 255
+    }
+    define wall {
+        use white
+        normal 1 0 0
+    }
+    plane {
+        use wall
+    }
+}",
+        )
+        .unwrap();
+        assert_eq!(numbers(&tree, "plane color"), [255., 255., 255.]);
+        assert_eq!(numbers(&tree, "plane normal"), [1., 0., 0.]);
+    }
+
+    #[test]
+    fn materials() {
+        let tree = expanded(
+            "scene {
+    materials {
+        glossy-white {
+            color 255 255 255
+            shininess 10
+        }
+        red {
+            material glossy-white
+            color 255 0 0
+        }
+    }
+    objects {
+        sphere {
+            material red
+        }
+        sphere {
+            material glossy-white
+            color 0 0 255
+        }
+    }
+}",
+        )
+        .unwrap();
+        assert!(tree.get_path("materials").is_err());
+        let objects = tree.get_path("objects").unwrap();
+        let (red, blue) = (&objects.children[0], &objects.children[1]);
+        assert_eq!(numbers(red, "color"), [255., 0., 0.]);
+        assert_eq!(numbers(red, "shininess"), [10.]);
+        assert_eq!(numbers(blue, "color"), [0., 0., 255.]);
+        assert!(red.get_path("material").is_err());
+    }
+
+    #[test]
+    fn quoted_names() {
+        let tree = expanded(
+            "scene {
+    materials {
+        red {
+            color 255 0 0
+        }
+    }
+    sphere {
+        material \"red\"
+    }
+}",
+        )
+        .unwrap();
+        assert_eq!(numbers(&tree, "sphere color"), [255., 0., 0.]);
+        let err = expanded("scene {\n    sphere {\n        material \"not a name\"\n    }\n}")
+            .unwrap_err();
+        assert!(err.message.contains("a name"), "{err}");
+    }
+
+    #[test]
+    fn undefined_material() {
+        let err = expanded(
+            "scene {
+    materials {
+        glossy-white {
+            color 255 255 255
+        }
+    }
+    sphere {
+        material glosy-white
+    }
+}",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "scene > sphere > material (line 8): \
+             undefined material \"glosy-white\" (did you mean \"glossy-white\"?)"
+        );
+    }
+
+    #[test]
+    fn undefined_definition() {
+        let err = expanded("scene {\n    use nothing\n}").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "scene > use (line 2): undefined definition \"nothing\""
+        );
+    }
+
+    #[test]
+    fn circular_definitions() {
+        let err = expanded(
+            "scene {
+    define a {
+        use b
+    }
+    define b {
+        use a
+    }
+    use a
+}",
+        )
+        .unwrap_err();
+        assert_eq!(err.message, "circular reference: a -> b -> a");
+    }
+
+    #[test]
+    fn circular_materials() {
+        let err = expanded(
+            "scene {
+    materials {
+        a {
+            material b
+        }
+        b {
+            material a
+        }
+    }
+    sphere {
+        material a
+    }
+}",
+        )
+        .unwrap_err();
+        assert_eq!(err.message, "circular reference: a -> b -> a");
+    }
+
+    #[test]
+    fn duplicate_definition() {
+        let err = expanded(
+            "scene {
+    define a {
+    }
+    define a {
+    }
+}",
+        )
+        .unwrap_err();
+        assert_eq!(err.message, "\"a\" is already defined on line 2");
+    }
+}
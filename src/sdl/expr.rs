@@ -0,0 +1,407 @@
+//! Arithmetic expressions in SDL values, e.g. `2*r+1` or `(sin(pi / 4) * room)`.
+//!
+//! Precedence, from loosest to tightest: `+ -`, `* / %`, unary `-`, `^`.
+//! `^` is right-associative. Angles are in radians.
+
+use super::parse::{ParseErrorKind, MAX_NESTING};
+
+/// Where in the expression text an error occurred.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExprError {
+    pub kind: ParseErrorKind,
+    /// Offset of the offending token, in chars.
+    pub offset: usize,
+    pub token: String,
+}
+
+/// Evaluates `text`, looking up any variables with `lookup`.
+pub fn eval(text: &str, lookup: &dyn Fn(&str) -> Option<f64>) -> Result<f64, ExprError> {
+    let tokens = tokenize(text)?;
+    let mut parser = Parser {
+        tokens: &tokens,
+        pos: 0,
+        depth: 0,
+        lookup,
+        end: text.chars().count(),
+    };
+    let value = parser.expr()?;
+    if let Some(token) = parser.peek() {
+        return Err(token.error(ParseErrorKind::InvalidExpression));
+    }
+    if !value.is_finite() {
+        return Err(ExprError {
+            kind: ParseErrorKind::NonFiniteResult,
+            offset: 0,
+            token: text.to_owned(),
+        });
+    }
+    Ok(value)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Number(f64),
+    Name(String),
+    /// One of `+ - * / % ^ ( ) ,`.
+    Symbol(char),
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    offset: usize,
+    text: String,
+}
+
+impl Token {
+    fn error(&self, kind: ParseErrorKind) -> ExprError {
+        ExprError {
+            kind,
+            offset: self.offset,
+            token: self.text.clone(),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, ExprError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let kind = if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if c.is_ascii_digit() || c == '.' {
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            // Exponent, e.g. `1e-3`.
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let sign = chars.get(i + 1).is_some_and(|&c| c == '+' || c == '-');
+                let digit_at = if sign { i + 2 } else { i + 1 };
+                if chars.get(digit_at).is_some_and(char::is_ascii_digit) {
+                    i = digit_at;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let number: String = chars[start..i].iter().collect();
+            match number.parse() {
+                Ok(n) => TokenKind::Number(n),
+                Err(_) => {
+                    return Err(ExprError {
+                        kind: ParseErrorKind::InvalidExpression,
+                        offset: start,
+                        token: number,
+                    })
+                }
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            TokenKind::Name(chars[start..i].iter().collect())
+        } else if "+-*/%^(),".contains(c) {
+            i += 1;
+            TokenKind::Symbol(c)
+        } else {
+            return Err(ExprError {
+                kind: ParseErrorKind::InvalidExpression,
+                offset: start,
+                token: c.to_string(),
+            });
+        };
+        tokens.push(Token {
+            kind,
+            offset: start,
+            text: chars[start..i].iter().collect(),
+        });
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    /// How many unary operators, parentheses and calls enclose `pos`.
+    depth: usize,
+    lookup: &'a dyn Fn(&str) -> Option<f64>,
+    /// Length of the text, for errors at the end of input.
+    end: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<&Token, ExprError> {
+        let token = self.tokens.get(self.pos).ok_or(ExprError {
+            kind: ParseErrorKind::InvalidExpression,
+            offset: self.end,
+            token: String::new(),
+        })?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    /// An error at the next token, or at the end if there is none.
+    fn error_here(&self, kind: ParseErrorKind) -> ExprError {
+        self.peek().map_or(
+            ExprError {
+                kind,
+                offset: self.end,
+                token: String::new(),
+            },
+            |token| token.error(kind),
+        )
+    }
+
+    /// Consumes the next token if it's the symbol `c`.
+    fn eat(&mut self, c: char) -> bool {
+        let found = matches!(self.peek(), Some(t) if t.kind == TokenKind::Symbol(c));
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ExprError> {
+        let token = self.next()?;
+        if token.kind != TokenKind::Symbol(c) {
+            return Err(token.error(ParseErrorKind::InvalidExpression));
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self) -> Result<f64, ExprError> {
+        let mut value = self.term()?;
+        loop {
+            if self.eat('+') {
+                value += self.term()?;
+            } else if self.eat('-') {
+                value -= self.term()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<f64, ExprError> {
+        let mut value = self.unary()?;
+        loop {
+            if self.eat('*') {
+                value *= self.unary()?;
+            } else if self.eat('/') {
+                value /= self.unary()?;
+            } else if self.eat('%') {
+                value %= self.unary()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// Every level of nesting passes through here, so this is where the
+    /// depth is limited, before it can overflow the stack.
+    fn unary(&mut self) -> Result<f64, ExprError> {
+        if self.depth >= MAX_NESTING {
+            return Err(self.error_here(ParseErrorKind::TooDeep));
+        }
+        self.depth += 1;
+        let value = self.signed();
+        self.depth -= 1;
+        value
+    }
+
+    fn signed(&mut self) -> Result<f64, ExprError> {
+        if self.eat('-') {
+            Ok(-self.unary()?)
+        } else if self.eat('+') {
+            self.unary()
+        } else {
+            self.power()
+        }
+    }
+
+    fn power(&mut self) -> Result<f64, ExprError> {
+        let base = self.atom()?;
+        if self.eat('^') {
+            // Right-associative, and binds tighter than unary minus on its
+            // left: `-2^2` is -4.
+            Ok(base.powf(self.unary()?))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn atom(&mut self) -> Result<f64, ExprError> {
+        let token = self.next()?.clone();
+        match &token.kind {
+            TokenKind::Number(n) => Ok(*n),
+            TokenKind::Symbol('(') => {
+                let value = self.expr()?;
+                self.expect(')')?;
+                Ok(value)
+            }
+            TokenKind::Name(name) if self.eat('(') => {
+                let mut args = Vec::new();
+                if !self.eat(')') {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.eat(')') {
+                            break;
+                        }
+                        self.expect(',')?;
+                    }
+                }
+                call(name, &args).map_err(|kind| token.error(kind))
+            }
+            TokenKind::Name(name) => (self.lookup)(name)
+                .or_else(|| constant(name))
+                .ok_or_else(|| token.error(ParseErrorKind::UndefinedVariable)),
+            TokenKind::Symbol(_) => Err(token.error(ParseErrorKind::InvalidExpression)),
+        }
+    }
+}
+
+fn constant(name: &str) -> Option<f64> {
+    use std::f64::consts;
+    match name {
+        "pi" => Some(consts::PI),
+        "tau" => Some(consts::TAU),
+        "e" => Some(consts::E),
+        _ => None,
+    }
+}
+
+fn call(name: &str, args: &[f64]) -> Result<f64, ParseErrorKind> {
+    let unary: Option<fn(f64) -> f64> = match name {
+        "sin" => Some(f64::sin),
+        "cos" => Some(f64::cos),
+        "tan" => Some(f64::tan),
+        "asin" => Some(f64::asin),
+        "acos" => Some(f64::acos),
+        "atan" => Some(f64::atan),
+        "sqrt" => Some(f64::sqrt),
+        "abs" => Some(f64::abs),
+        "floor" => Some(f64::floor),
+        "ceil" => Some(f64::ceil),
+        "round" => Some(f64::round),
+        "exp" => Some(f64::exp),
+        "ln" => Some(f64::ln),
+        "radians" => Some(f64::to_radians),
+        "degrees" => Some(f64::to_degrees),
+        _ => None,
+    };
+    let binary: Option<fn(f64, f64) -> f64> = match name {
+        "atan2" => Some(f64::atan2),
+        "pow" => Some(f64::powf),
+        "min" => Some(f64::min),
+        "max" => Some(f64::max),
+        _ => None,
+    };
+    match (unary, binary, args) {
+        (Some(f), _, &[x]) => Ok(f(x)),
+        (_, Some(f), &[x, y]) => Ok(f(x, y)),
+        (Some(_), _, _) | (_, Some(_), _) => Err(ParseErrorKind::WrongArgumentCount),
+        (None, None, _) => Err(ParseErrorKind::UnknownFunction),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval_with(text: &str, vars: &[(&str, f64)]) -> Result<f64, ExprError> {
+        eval(text, &|name| {
+            vars.iter().find(|(n, _)| *n == name).map(|&(_, v)| v)
+        })
+    }
+
+    fn eval_ok(text: &str) -> f64 {
+        eval_with(text, &[("r", 2.), ("room", 30.)]).unwrap()
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(eval_ok("2*r+1"), 5.);
+        assert_eq!(eval_ok("(-room)"), -30.);
+        assert_eq!(eval_ok("1 + 2 * 3"), 7.);
+        assert_eq!(eval_ok("(1 + 2) * 3"), 9.);
+        assert_eq!(eval_ok("7 % 4"), 3.);
+        assert_eq!(eval_ok("10 - 4 - 3"), 3.);
+        assert_eq!(eval_ok("2^3^2"), 512.);
+        assert_eq!(eval_ok("-2^2"), -4.);
+        assert_eq!(eval_ok("1.5e1"), 15.);
+        assert_eq!(eval_ok("2e-1 * 10"), 2.);
+    }
+
+    #[test]
+    fn functions_and_constants() {
+        assert_eq!(eval_ok("sqrt(16)"), 4.);
+        assert!((eval_ok("sin(pi / 2)") - 1.).abs() < 1e-12);
+        assert!((eval_ok("cos(radians(180))") + 1.).abs() < 1e-12);
+        assert_eq!(eval_ok("max(r, 3)"), 3.);
+        assert_eq!(eval_ok("min(r, 3)"), 2.);
+    }
+
+    #[test]
+    fn variables_shadow_constants() {
+        assert_eq!(eval_with("e", &[("e", 1.)]).unwrap(), 1.);
+    }
+
+    #[test]
+    fn errors() {
+        let err = |text| eval_with(text, &[("r", 1.)]).unwrap_err();
+
+        let e = err("2*rr");
+        assert_eq!(
+            (e.kind, e.offset, e.token.as_str()),
+            (ParseErrorKind::UndefinedVariable, 2, "rr")
+        );
+
+        let e = err("1 + cot(r)");
+        assert_eq!((e.kind, e.offset), (ParseErrorKind::UnknownFunction, 4));
+
+        let e = err("sqrt(1, 2)");
+        assert_eq!(e.kind, ParseErrorKind::WrongArgumentCount);
+
+        let e = err("(1 + 2");
+        assert_eq!((e.kind, e.offset), (ParseErrorKind::InvalidExpression, 6));
+
+        let e = err("1 $ 2");
+        assert_eq!(
+            (e.kind, e.offset, e.token.as_str()),
+            (ParseErrorKind::InvalidExpression, 2, "$")
+        );
+
+        let e = err("1 2");
+        assert_eq!((e.kind, e.offset), (ParseErrorKind::InvalidExpression, 2));
+
+        let e = err("1 / 0");
+        assert_eq!(e.kind, ParseErrorKind::NonFiniteResult);
+    }
+
+    #[test]
+    fn too_deep() {
+        assert_eq!(
+            eval_ok(&format!("{}1{}", "(".repeat(100), ")".repeat(100))),
+            1.
+        );
+        for text in [
+            format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000)),
+            "-".repeat(100_000) + "1",
+            "sqrt(".repeat(100_000) + "1",
+            "2^".repeat(100_000) + "1",
+        ] {
+            let e = eval_with(&text, &[]).unwrap_err();
+            assert_eq!(e.kind, ParseErrorKind::TooDeep, "{text:.20}");
+        }
+        let e = eval_with(&"(".repeat(MAX_NESTING + 1), &[]).unwrap_err();
+        assert_eq!((e.kind, e.offset), (ParseErrorKind::TooDeep, MAX_NESTING));
+    }
+}
@@ -0,0 +1,138 @@
+//! Conversion from SDL nodes into typed values.
+//!
+//! Struct types are declared with [`sdl_struct!`], which generates both the
+//! conversion and the schema used by [`validate`](super::validate), so the
+//! two can't drift apart.
+
+use super::{schema::FieldType, Node, NodeError, Value};
+
+pub trait FromNode: Sized {
+    /// Describes this type to the schema validator.
+    const TYPE: FieldType;
+
+    /// Expects a tree that has been through `validate`, which fills in
+    /// defaults for missing optional keys.
+    fn from_node(node: &Node) -> Result<Self, NodeError>;
+}
+
+impl Node {
+    /// Converts the child named `key`.
+    pub fn field<T: FromNode>(&self, key: &str) -> Result<T, NodeError> {
+        T::from_node(self.get_path(key)?)
+    }
+
+    /// The values of a leaf node, which must have exactly `n` of them.
+    ///
+    /// `type_name` is only used for error messages.
+    pub fn leaf_values(&self, type_name: &str, n: usize) -> Result<&[Value], NodeError> {
+        if !self.children.is_empty() {
+            Err(self.error(format!(
+                "cannot convert to {type_name}. node has {} children (expected 0)",
+                self.children.len()
+            )))?
+        }
+        if self.values.len() != n {
+            Err(self.error(format!(
+                "cannot convert to {type_name}. node has {} values (expected {n})",
+                self.values.len()
+            )))?
+        }
+        Ok(&self.values)
+    }
+
+    /// Like `leaf_values`, but the values must all be numbers.
+    pub fn leaf_numbers<const N: usize>(&self, type_name: &str) -> Result<[f64; N], NodeError> {
+        let values = self.leaf_values(type_name, N)?;
+        let mut numbers = [0.; N];
+        for (n, value) in numbers.iter_mut().zip(values) {
+            *n = match value {
+                Value::Number(x) => *x,
+                other => Err(self.mismatch(type_name, "a number", other))?,
+            };
+        }
+        Ok(numbers)
+    }
+
+    /// An error for a value of the wrong kind.
+    pub fn mismatch(&self, type_name: &str, expected: &str, found: &Value) -> NodeError {
+        self.error(format!(
+            "cannot convert to {type_name}. expected {expected}, found {} {found}",
+            found.kind()
+        ))
+    }
+
+    /// Checks that this node is a block: children only, no values.
+    pub fn expect_block(&self, type_name: &str) -> Result<(), NodeError> {
+        if !self.values.is_empty() {
+            Err(self.error(format!(
+                "cannot convert to {type_name}. node has {} values (expected 0)",
+                self.values.len()
+            )))?
+        }
+        Ok(())
+    }
+}
+
+/// A `FieldType::Value` checker for any leaf type.
+pub fn check<T: FromNode>(node: &Node) -> Result<(), NodeError> {
+    T::from_node(node).map(drop)
+}
+
+/// Implements [`FromNode`] for a struct whose fields each correspond to one
+/// key in its SDL block.
+///
+/// A field's key is its name, unless overridden with `#[key = "..."]`.
+/// Fields with `= [...]` are optional, and default to those values.
+///
+/// ```ignore
+/// sdl_struct! {
+///     Camera {
+///         position: Vec3,
+///         up: Vec3 = [0, 1, 0],
+///         #[key = "look-at"]
+///         look_at: Vec3,
+///     }
+/// }
+/// ```
+macro_rules! sdl_struct {
+    (
+        $ty:ident {
+            $(
+                $(#[key = $key:literal])?
+                $field:ident : $field_ty:ty $(= [$($default:expr),* $(,)?])?
+            ),* $(,)?
+        }
+    ) => {
+        impl $crate::sdl::from_node::FromNode for $ty {
+            const TYPE: $crate::sdl::schema::FieldType =
+                $crate::sdl::schema::FieldType::Block(&$crate::sdl::schema::Schema {
+                    fields: &[$(
+                        $crate::sdl::schema::Field {
+                            name: sdl_struct!(@key $field $($key)?),
+                            ty: <$field_ty as $crate::sdl::from_node::FromNode>::TYPE,
+                            presence: sdl_struct!(@presence $([$($default),*])?),
+                        },
+                    )*],
+                });
+
+            fn from_node(
+                node: &$crate::sdl::Node,
+            ) -> ::std::result::Result<Self, $crate::sdl::NodeError> {
+                node.expect_block(stringify!($ty))?;
+                Ok(Self {
+                    $($field: node.field(sdl_struct!(@key $field $($key)?))?,)*
+                })
+            }
+        }
+    };
+
+    (@key $field:ident) => { stringify!($field) };
+    (@key $field:ident $key:literal) => { $key };
+
+    (@presence) => { $crate::sdl::schema::Presence::Required };
+    (@presence [$($default:expr),*]) => {
+        $crate::sdl::schema::Presence::Default(&[$($default as f64),*])
+    };
+}
+
+pub(crate) use sdl_struct;
@@ -0,0 +1,202 @@
+//! `include "walls.sdl"` splices the top-level nodes of another file into the
+//! block where it appears. Paths are relative to the including file.
+
+use std::{
+    fs, mem,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::error::{Error, Result};
+
+use super::{
+    parse::{parse_nodes, parse_root, MAX_GENERATED_NODES},
+    FromNode, Node,
+};
+
+/// Reads and parses a scene file, including any files it refers to.
+pub fn parse_file(path: &Path) -> Result<Node> {
+    let text = fs::read_to_string(path).map_err(|e| Error::read(path, e))?;
+    let name: Rc<str> = path.display().to_string().into();
+    let mut tree = parse_root(&text, Some(&name))?;
+
+    let canonical = fs::canonicalize(path).map_err(|e| Error::read(path, e))?;
+    let mut stack = vec![(canonical, name.to_string())];
+    let mut budget = MAX_GENERATED_NODES.saturating_sub(tree.size());
+    splice_includes(&mut tree, directory(path), &mut stack, &mut budget)?;
+    tree.assign_paths(tree.name.clone());
+    Ok(tree)
+}
+
+/// Resolves includes in a tree that wasn't read from a file, relative to
+/// `base_dir`.
+pub fn resolve_includes(tree: &mut Node, base_dir: &Path) -> Result<()> {
+    let mut budget = MAX_GENERATED_NODES.saturating_sub(tree.size());
+    splice_includes(tree, base_dir, &mut vec![], &mut budget)?;
+    tree.assign_paths(tree.name.clone());
+    Ok(())
+}
+
+pub(super) fn directory(path: &Path) -> &Path {
+    path.parent().unwrap_or(Path::new("."))
+}
+
+/// `stack` holds the files currently being included, both canonicalized (for
+/// comparison) and as displayed to the user. `budget` is how many more nodes
+/// the included files may add, shared by all of them.
+fn splice_includes(
+    node: &mut Node,
+    dir: &Path,
+    stack: &mut Vec<(PathBuf, String)>,
+    budget: &mut usize,
+) -> Result<()> {
+    for mut child in mem::take(&mut node.children) {
+        if child.name != "include" {
+            splice_includes(&mut child, dir, stack, budget)?;
+            node.children.push(child);
+            continue;
+        }
+
+        if !child.children.is_empty() {
+            Err(Error::Reference(
+                child.error("`include` can't have a block"),
+            ))?
+        }
+        let path = dir.join(String::from_node(&child).map_err(Error::Reference)?);
+        let name = path.display().to_string();
+        // Say which include it was, as well as which file.
+        let cannot_read = |source| Error::Io {
+            path: path.clone(),
+            source,
+            context: format!("cannot read the file included on {}", child.span),
+        };
+
+        let canonical = fs::canonicalize(&path).map_err(cannot_read)?;
+        if let Some(idx) = stack.iter().position(|(p, _)| *p == canonical) {
+            let cycle: Vec<_> = stack[idx..]
+                .iter()
+                .map(|(_, n)| n.as_str())
+                .chain([name.as_str()])
+                .collect();
+            let message = format!("include cycle: {}", cycle.join(" -> "));
+            Err(Error::Reference(child.error(message)))?
+        }
+
+        let text = fs::read_to_string(&path).map_err(cannot_read)?;
+        let mut included = Node {
+            children: parse_nodes(&text, Some(&name.as_str().into()), *budget)?,
+            ..child
+        };
+        let size = included.size() - 1;
+        if size > *budget {
+            Err(Error::Reference(included.error(format!(
+                "too many nodes generated by include, more than {MAX_GENERATED_NODES}"
+            ))))?
+        }
+        *budget -= size;
+        // So that errors in the included file have a sensible path.
+        included.assign_paths(node.path.clone());
+        stack.push((canonical, name));
+        splice_includes(&mut included, directory(&path), stack, budget)?;
+        stack.pop();
+        node.children.extend(included.children);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for one test's files.
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ray-tracer-{}-{test}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn splices_nodes() {
+        let dir = temp_dir("splices_nodes");
+        fs::create_dir(dir.join("rooms")).unwrap();
+        fs::write(
+            dir.join("scene.sdl"),
+            "scene {\n    objects {\n        include \"rooms/box.sdl\"\n    }\n}",
+        )
+        .unwrap();
+        // Relative to rooms/, not to the top-level file.
+        fs::write(
+            dir.join("rooms/box.sdl"),
+            "plane {\n    anchor 0 0 0\n}\ninclude \"walls.sdl\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("rooms/walls.sdl"), "plane {\n}\nplane {\n}\n").unwrap();
+
+        let tree = parse_file(&dir.join("scene.sdl")).unwrap();
+        let objects = tree.get_path("objects").unwrap();
+        assert_eq!(objects.children.len(), 3);
+        assert_eq!(objects.children[2].path, "scene > objects > plane[2]");
+        let file = objects.children[2].span.file.as_deref().unwrap();
+        assert!(file.ends_with("walls.sdl"), "{file}");
+        assert_eq!(objects.children[2].span.line, 3);
+    }
+
+    #[test]
+    fn include_cycle() {
+        let dir = temp_dir("include_cycle");
+        fs::write(dir.join("a.sdl"), "scene {\n    include \"b.sdl\"\n}").unwrap();
+        fs::write(dir.join("b.sdl"), "include \"a.sdl\"").unwrap();
+
+        let err = parse_file(&dir.join("a.sdl")).unwrap_err().to_string();
+        let err = err.replace(&format!("{}/", dir.display()), "");
+        assert_eq!(
+            err,
+            "scene > include (line 1 of b.sdl): include cycle: a.sdl -> b.sdl -> a.sdl"
+        );
+    }
+
+    #[test]
+    fn missing_file() {
+        let dir = temp_dir("missing_file");
+        fs::write(dir.join("a.sdl"), "scene {\n    include \"nope.sdl\"\n}").unwrap();
+
+        let err = parse_file(&dir.join("a.sdl")).unwrap_err();
+        let Error::Io { ref path, .. } = err else {
+            panic!("{err:?}");
+        };
+        assert_eq!(path, &dir.join("nope.sdl"));
+        let err = err.to_string().replace(&format!("{}/", dir.display()), "");
+        assert!(
+            err.starts_with("cannot read the file included on line 2 of a.sdl \"nope.sdl\": "),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn too_many_nodes() {
+        let dir = temp_dir("too_many_nodes");
+        fs::write(
+            dir.join("a.sdl"),
+            "scene {\n    include \"b.sdl\"\n    include \"b.sdl\"\n}",
+        )
+        .unwrap();
+        // Within the limit alone, but not twice over.
+        fs::write(dir.join("b.sdl"), "repeat i 0 40000 {\n    x\n}").unwrap();
+
+        let err = parse_file(&dir.join("a.sdl")).unwrap_err().to_string();
+        assert!(err.starts_with("too many nodes generated by"), "{err}");
+    }
+
+    #[test]
+    fn syntax_error_names_file() {
+        let dir = temp_dir("syntax_error_names_file");
+        fs::write(dir.join("a.sdl"), "scene {\n    include \"b.sdl\"\n}").unwrap();
+        fs::write(dir.join("b.sdl"), "plane {\n    anchor 0 0 zero!\n}").unwrap();
+
+        let err = parse_file(&dir.join("a.sdl")).unwrap_err().to_string();
+        let first_line = err.lines().next().unwrap();
+        assert!(first_line.starts_with("invalid value \"zero!\" at line 2, column 16 of "));
+        assert!(first_line.ends_with("b.sdl"), "{first_line}");
+    }
+}
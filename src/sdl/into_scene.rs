@@ -1,219 +1,281 @@
-// todo: these conversion methods seem repetitive and error prone
-// What to do about it?
-// Maybe tests would be a good start, but the code could be better too.
-
-// todo: refactor f64 -> uX conversion code dup
-
 use crate::{
     color::Color,
-    error::{Error, Result},
     objects::{Object, Plane, Sphere},
+    scene::{check_geometry, BuildError, Camera, Light},
     vec3::Vec3,
 };
 
-use super::Node;
+use super::{
+    from_node::{check, sdl_struct, FromNode},
+    registry,
+    schema::FieldType,
+    Node, NodeError, Value,
+};
 
-impl TryFrom<&Node> for f64 {
-    type Error = Error;
+/// The top-level `scene` block, as written in the file.
+pub struct SceneFile {
+    pub camera: Camera,
+    pub focal_distance: f64,
+    pub screen: Screen,
+    pub lights: Lights,
+    pub objects: Vec<Box<dyn Object>>,
+}
 
-    fn try_from(node: &Node) -> Result<Self> {
-        if !node.children.is_empty() {
-            Err(format!(
-                "cannot convert to f64. node has {} children (expected 0)",
-                node.children.len()
-            ))?
-        }
-        if node.values.len() != 1 {
-            Err(format!(
-                "cannot convert to f64. node has {} values (expected 1)",
-                node.values.len()
-            ))?
-        }
-        Ok(node.values[0])
+sdl_struct! {
+    SceneFile {
+        camera: Camera,
+        #[key = "focal-distance"]
+        focal_distance: f64,
+        screen: Screen,
+        lights: Lights,
+        objects: Vec<Box<dyn Object>>,
+    }
+}
+
+impl SceneFile {
+    /// Checks for geometry that converts fine but can't be rendered. `tree`
+    /// is what the scene was converted from, for locating errors.
+    pub fn check_geometry(&self, tree: &Node) -> Result<(), NodeError> {
+        let Screen { width, height } = self.screen;
+        let Err(e) = check_geometry(self.focal_distance, (width, height), &self.objects) else {
+            return Ok(());
+        };
+        let (node, message) = match e {
+            BuildError::InvalidFocalDistance(d) => (
+                tree.get_path("focal-distance")?,
+                format!("focal-distance must be positive, found {d}"),
+            ),
+            BuildError::EmptyScreen { .. } => (tree.get_path("screen")?, e.to_string()),
+            BuildError::InvalidObject { index, message } => {
+                let objects = tree.get_path("objects")?;
+                (objects.children.get(index).unwrap_or(objects), message)
+            }
+            BuildError::Missing(_) => unreachable!("the schema requires every part"),
+        };
+        Err(node.error(message))
     }
 }
 
-impl TryFrom<&Node> for u32 {
-    type Error = Error;
+pub struct Screen {
+    pub width: u32,
+    pub height: u32,
+}
 
-    fn try_from(node: &Node) -> Result<Self> {
-        if !node.children.is_empty() {
-            Err(format!(
-                "cannot convert to u32. node has {} children (expected 0)",
-                node.children.len()
-            ))?
-        }
-        if node.values.len() != 1 {
-            Err(format!(
-                "cannot convert to u32. node has {} values (expected 1)",
-                node.values.len()
-            ))?
-        }
-        let value = node.values[0];
-        if value.fract() != 0. {
-            Err(format!(
-                "cannot convert to u32. value has fractional component: {}",
-                value
-            ))?
-        }
-        if value.clamp(0., u32::MAX as f64) != value {
-            Err(format!(
-                "cannot convert to u32. value out of range for u32: {}",
-                value
-            ))?
-        }
-        Ok(value as u32)
+sdl_struct! {
+    Screen {
+        width: u32,
+        height: u32,
     }
 }
 
-impl TryFrom<&Node> for Vec3 {
-    type Error = Error;
+// todo: support more than one light
+pub struct Lights {
+    pub light: Light,
+}
 
-    fn try_from(node: &Node) -> Result<Self> {
-        if !node.children.is_empty() {
-            Err(format!(
-                "cannot convert to Vec3. node has {} children (expected 0)",
-                node.children.len()
-            ))?
-        }
-        if node.values.len() != 3 {
-            Err(format!(
-                "cannot convert to Vec3. node has {} values (expected 3)",
-                node.values.len()
-            ))?
-        }
-        Ok(Vec3::new(node.values[0], node.values[1], node.values[2]))
+sdl_struct! {
+    Lights {
+        light: Light,
     }
 }
 
-impl TryFrom<&Node> for Color {
-    type Error = Error;
+sdl_struct! {
+    Camera {
+        position: Vec3,
+        up: Vec3 = [0, 1, 0],
+        right: Vec3 = [1, 0, 0],
+    }
+}
 
-    fn try_from(node: &Node) -> Result<Self> {
-        if !node.children.is_empty() {
-            Err(format!(
-                "cannot convert to Color. node has {} children (expected 0)",
-                node.children.len()
-            ))?
-        }
-        if node.values.len() != 3 {
-            Err(format!(
-                "cannot convert to Color. node has {} values (expected 3)",
-                node.values.len()
-            ))?
-        }
-        for i in 0..3 {
-            if node.values[i].fract() != 0. {
-                Err(format!(
-                    "cannot convert to Color. node has fractional value {}",
-                    node.values[i]
-                ))?
-            }
-            if node.values[i].clamp(0., 255.) != node.values[i] {
-                Err(format!(
-                    "cannot convert to Color. node value out of range 0-255: {}",
-                    node.values[i]
-                ))?
-            }
-        }
-        Ok(Color::new(
-            node.values[0] as u8,
-            node.values[1] as u8,
-            node.values[2] as u8,
-        ))
+sdl_struct! {
+    Light {
+        position: Vec3,
+        color: Color = [255, 255, 255],
+        intensity: f64 = [1],
     }
 }
 
-impl TryFrom<&Node> for Vec<Sphere> {
-    type Error = Error;
+sdl_struct! {
+    Sphere {
+        color: Color = [255, 255, 255],
+        center: Vec3,
+        radius: f64,
+    }
+}
 
-    fn try_from(node: &Node) -> Result<Self> {
-        let fail = "cannot convert to Vec<Sphere>:";
-        if !node.values.is_empty() {
-            Err(format!(
-                "{fail} node has {} values (expected 0)",
-                node.values.len()
-            ))?
-        }
-        node.children
-            .iter()
-            .map(TryFrom::try_from)
-            .collect::<Result<Self>>()
-            .map_err(|e| format!("{fail} {e}").into())
+sdl_struct! {
+    Plane {
+        anchor: Vec3,
+        normal: Vec3,
+        color: Color = [255, 255, 255],
     }
 }
 
-impl TryFrom<&Node> for Sphere {
-    type Error = Error;
+impl FromNode for f64 {
+    const TYPE: FieldType = FieldType::Value(check::<Self>);
 
-    fn try_from(node: &Node) -> Result<Self> {
-        let fail = "cannot convert to Sphere:";
-        if !node.values.is_empty() {
-            Err(format!(
-                "{fail} node has {} values (expected 0)",
-                node.values.len()
-            ))?
-        }
-        Ok(Self {
-            color: node.get_path("color")?.try_into()?,
-            center: node.get_path("center")?.try_into()?,
-            radius: node.get_path("radius")?.try_into()?,
-        })
+    fn from_node(node: &Node) -> Result<Self, NodeError> {
+        let [x] = node.leaf_numbers("f64")?;
+        Ok(x)
     }
 }
 
-impl TryFrom<&Node> for Plane {
-    type Error = Error;
+impl FromNode for u32 {
+    const TYPE: FieldType = FieldType::Value(check::<Self>);
 
-    fn try_from(node: &Node) -> Result<Self> {
-        let fail = "cannot convert to Plane:";
-        if !node.values.is_empty() {
-            Err(format!(
-                "{fail} node has {} values (expected 0)",
-                node.values.len()
-            ))?
-        }
-        Ok(Self {
-            anchor: node.get_path("anchor")?.try_into()?,
-            normal: node.get_path("normal")?.try_into()?,
-            color: node.get_path("color")?.try_into()?,
-        })
+    fn from_node(node: &Node) -> Result<Self, NodeError> {
+        let [x] = node.leaf_numbers("u32")?;
+        Ok(integer(node, "u32", x, u32::MAX.into())? as u32)
     }
 }
 
-// todo: reduce code dup b/w this and Vec<Sphere>
-// (or just remove vec sphere entirely)
+impl FromNode for Vec3 {
+    const TYPE: FieldType = FieldType::Value(check::<Self>);
 
-impl TryFrom<&Node> for Vec<Box<dyn Object>> {
-    type Error = Error;
+    fn from_node(node: &Node) -> Result<Self, NodeError> {
+        let [x, y, z] = node.leaf_numbers("Vec3")?;
+        Ok(Vec3::new(x, y, z))
+    }
+}
+
+impl FromNode for Color {
+    const TYPE: FieldType = FieldType::Value(check::<Self>);
+
+    fn from_node(node: &Node) -> Result<Self, NodeError> {
+        let [r, g, b] = node.leaf_numbers("Color")?;
+        let channel = |value| Ok(integer(node, "Color", value, 255.)? as u8);
+        Ok(Color::new(channel(r)?, channel(g)?, channel(b)?))
+    }
+}
+
+impl FromNode for bool {
+    const TYPE: FieldType = FieldType::Value(check::<Self>);
 
-    fn try_from(node: &Node) -> Result<Self> {
-        let fail = "cannot convert to Vec<Box<dyn Object>>:";
-        if !node.values.is_empty() {
-            Err(format!(
-                "{fail} node has {} values (expected 0)",
-                node.values.len()
-            ))?
+    fn from_node(node: &Node) -> Result<Self, NodeError> {
+        match &node.leaf_values("bool", 1)?[0] {
+            Value::Bool(b) => Ok(*b),
+            other => Err(node.mismatch("bool", "true/on or false/off", other)),
         }
-        node.children
-            .iter()
-            .map(|node| {
-                // todo: there must be a nice way to short-circuit here
-                dyn_sphere(node).or(dyn_plane(node))
-            })
-            .collect::<Result<Self>>()
-            .map_err(|e| format!("{fail} {e}").into())
     }
 }
 
-// todo: understand why the below works, and how to simplify it.
+/// A quoted string, e.g. a file name.
+impl FromNode for String {
+    const TYPE: FieldType = FieldType::Value(check::<Self>);
+
+    fn from_node(node: &Node) -> Result<Self, NodeError> {
+        match &node.leaf_values("String", 1)?[0] {
+            Value::String(s) => Ok(s.clone()),
+            other => Err(node.mismatch("String", "a quoted string", other)),
+        }
+    }
+}
+
+/// Checks that `value` is a whole number in the range `0..=max`.
+fn integer(node: &Node, type_name: &str, value: f64, max: f64) -> Result<f64, NodeError> {
+    if value.fract() != 0. {
+        Err(node.error(format!(
+            "cannot convert to {type_name}. value has fractional component: {value}"
+        )))?
+    }
+    if value.clamp(0., max) != value {
+        Err(node.error(format!(
+            "cannot convert to {type_name}. value out of range 0-{max}: {value}"
+        )))?
+    }
+    Ok(value)
+}
+
+impl FromNode for Vec<Box<dyn Object>> {
+    const TYPE: FieldType = FieldType::Objects;
 
-fn dyn_sphere(node: &Node) -> Result<Box<dyn Object>> {
-    let sphere: Result<Sphere> = node.try_into();
-    Ok(sphere.map(Box::new)?)
+    fn from_node(node: &Node) -> Result<Self, NodeError> {
+        node.expect_block("Vec<Box<dyn Object>>")?;
+        let registry = registry::registered();
+        node.children.iter().map(|ch| registry.parse(ch)).collect()
+    }
 }
 
-fn dyn_plane(node: &Node) -> Result<Box<dyn Object>> {
-    let plane: Result<Plane> = node.try_into();
-    Ok(plane.map(Box::new)?)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_reports_path_and_line() {
+        let tree: Node = "
+scene {
+    objects {
+        sphere {
+            center 0 0 0
+            radius 1
+        }
+        sphere {
+            center 0 0 0
+            radius 1 2
+        }
+    }
+}"
+        .parse()
+        .unwrap();
+        let sphere = &tree.get_path("objects").unwrap().children[1];
+        let err = sphere.field::<f64>("radius").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "scene > objects > sphere[1] > radius (line 10): \
+             cannot convert to f64. node has 2 values (expected 1)"
+        );
+    }
+
+    #[test]
+    fn missing_child_names_parent() {
+        let tree: Node = "scene {\n    screen {\n        width 10\n    }\n}"
+            .parse()
+            .unwrap();
+        let err = tree.get_path("screen height").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "scene > screen (line 2): no child named \"height\""
+        );
+    }
+
+    #[test]
+    fn color_out_of_range() {
+        let tree: Node = "scene {\n    color 0 256 0\n}".parse().unwrap();
+        let err = tree.field::<Color>("color").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "scene > color (line 2): cannot convert to Color. value out of range 0-255: 256"
+        );
+    }
+
+    #[test]
+    fn struct_fields() {
+        let tree: Node = "scene {\n    screen {\n        height 48\n        width 64\n    }\n}"
+            .parse()
+            .unwrap();
+        let screen: Screen = tree.field("screen").unwrap();
+        assert_eq!((screen.width, screen.height), (64, 48));
+    }
+
+    #[test]
+    fn typed_values() {
+        let tree: Node = "scene {
+    file \"teapot.obj\"
+    projection orthographic
+    shadows off
+}"
+        .parse()
+        .unwrap();
+        assert_eq!(tree.field::<String>("file").unwrap(), "teapot.obj");
+        assert!(!tree.field::<bool>("shadows").unwrap());
+
+        let err = tree.field::<f64>("projection").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "scene > projection (line 3): \
+             cannot convert to f64. expected a number, found identifier orthographic"
+        );
+        let err = tree.field::<String>("projection").unwrap_err();
+        assert!(err.message.contains("expected a quoted string"), "{err}");
+    }
 }
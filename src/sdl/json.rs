@@ -0,0 +1,651 @@
+//! Scenes written as JSON, for tools that would rather not emit SDL.
+//!
+//! A JSON document becomes the same tree as the equivalent SDL file:
+//!
+//! - The top-level object is the `scene` block.
+//! - An object is a block, and each of its keys is a child. Duplicate keys
+//!   are kept, as in SDL.
+//! - A number, boolean or string is a node with that one value. Strings stay
+//!   strings, even where SDL would have a name, e.g. `"material": "wall"`;
+//!   those places accept a string that's a valid name.
+//! - An array of values is a node with those values, e.g.
+//!   `"position": [0, 0, -20]`.
+//! - An array of objects is one block per object, all with the array's key,
+//!   e.g. `"sphere": [{ ... }, { ... }]`.
+
+use std::{fs, path::Path, rc::Rc};
+
+use crate::error::{self, Error};
+
+use super::{
+    include::{directory, resolve_includes},
+    parse::{is_valid_name, ParseError, ParseErrorKind, MAX_NESTING},
+    Node, Span, Value,
+};
+
+/// Reads and parses a JSON scene file, including any SDL files it refers to.
+pub fn parse_json_file(path: &Path) -> error::Result<Node> {
+    let text = fs::read_to_string(path).map_err(|e| Error::read(path, e))?;
+    let name: Rc<str> = path.display().to_string().into();
+    let mut tree = parse_json(&text, Some(&name))?;
+    resolve_includes(&mut tree, directory(path))?;
+    Ok(tree)
+}
+
+/// Parses a JSON scene into the tree the equivalent SDL would give.
+pub fn parse_json(text: &str, file: Option<&Rc<str>>) -> Result<Node, ParseError> {
+    let in_file = |mut e: ParseError| {
+        e.file = file.cloned();
+        e
+    };
+    let mut parser = Parser {
+        text,
+        chars: text.chars().collect(),
+        pos: 0,
+        line: 1,
+        column: 1,
+        depth: 0,
+    };
+    let json = parser.document().map_err(in_file)?;
+
+    let converter = Converter { text, file };
+    let mut root = converter.root(&json).map_err(in_file)?;
+    root.assign_paths(root.name.clone());
+    Ok(root)
+}
+
+/// A JSON value, and where it starts in the source.
+struct Json {
+    value: JsonValue,
+    /// 1-based line and column, counted in chars.
+    pos: (usize, usize),
+}
+
+enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(Key, Json)>),
+}
+
+/// An object key. `pos` is just inside the opening quote.
+struct Key {
+    name: String,
+    pos: (usize, usize),
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    column: usize,
+    /// How many arrays and objects the parser is inside.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, kind: ParseErrorKind, pos: (usize, usize), token: &str) -> ParseError {
+        ParseError::at(kind, pos, token, self.text)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|c| " \t\r\n".contains(c)) {
+            self.bump();
+        }
+    }
+
+    /// An error for whatever comes next, which isn't what was expected.
+    fn unexpected(&self) -> ParseError {
+        let pos = (self.line, self.column);
+        match self.peek() {
+            Some(c) => self.error(ParseErrorKind::UnexpectedToken, pos, &c.to_string()),
+            None => self.error(ParseErrorKind::UnexpectedEndOfInput, pos, ""),
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        self.skip_whitespace();
+        if self.peek() != Some(c) {
+            return Err(self.unexpected());
+        }
+        self.bump();
+        Ok(())
+    }
+
+    /// A single value, and nothing after it.
+    fn document(&mut self) -> Result<Json, ParseError> {
+        self.skip_whitespace();
+        if self.peek().is_none() {
+            return Err(self.error(ParseErrorKind::Empty, (1, 1), ""));
+        }
+        let json = self.value()?;
+        self.skip_whitespace();
+        if self.peek().is_some() {
+            return Err(self.unexpected());
+        }
+        Ok(json)
+    }
+
+    fn value(&mut self) -> Result<Json, ParseError> {
+        self.skip_whitespace();
+        let pos = (self.line, self.column);
+        let value = match self.peek() {
+            Some(c @ ('{' | '[')) => {
+                if self.depth >= MAX_NESTING {
+                    return Err(self.error(ParseErrorKind::TooDeep, pos, &c.to_string()));
+                }
+                self.depth += 1;
+                let value = if c == '{' {
+                    JsonValue::Object(self.object()?)
+                } else {
+                    JsonValue::Array(self.array()?)
+                };
+                self.depth -= 1;
+                value
+            }
+            Some('"') => JsonValue::String(self.string()?),
+            Some(c) if c == '-' || c.is_ascii_digit() => JsonValue::Number(self.number()?),
+            Some(c) if c.is_alphabetic() => {
+                let start = self.pos;
+                while self.peek().is_some_and(char::is_alphanumeric) {
+                    self.bump();
+                }
+                let word: String = self.chars[start..self.pos].iter().collect();
+                match word.as_str() {
+                    "true" => JsonValue::Bool(true),
+                    "false" => JsonValue::Bool(false),
+                    "null" => JsonValue::Null,
+                    _ => return Err(self.error(ParseErrorKind::UnexpectedToken, pos, &word)),
+                }
+            }
+            _ => return Err(self.unexpected()),
+        };
+        Ok(Json { value, pos })
+    }
+
+    fn object(&mut self) -> Result<Vec<(Key, Json)>, ParseError> {
+        self.bump(); // {
+        let mut entries = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.bump();
+            return Ok(entries);
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.unexpected());
+            }
+            let pos = (self.line, self.column + 1);
+            let key = Key {
+                name: self.string()?,
+                pos,
+            };
+            self.expect(':')?;
+            entries.push((key, self.value()?));
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.bump(),
+                Some('}') => {
+                    self.bump();
+                    return Ok(entries);
+                }
+                _ => return Err(self.unexpected()),
+            };
+        }
+    }
+
+    fn array(&mut self) -> Result<Vec<Json>, ParseError> {
+        self.bump(); // [
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.bump();
+            return Ok(items);
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.bump(),
+                Some(']') => {
+                    self.bump();
+                    return Ok(items);
+                }
+                _ => return Err(self.unexpected()),
+            };
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        let start = (self.pos, self.line, self.column);
+        let unterminated = |parser: &Self| {
+            let token: String = parser.chars[start.0..parser.pos].iter().collect();
+            let token = token.trim_end_matches(['\r', '\n']);
+            parser.error(
+                ParseErrorKind::UnterminatedString,
+                (start.1, start.2),
+                token,
+            )
+        };
+
+        self.bump(); // "
+        let mut string = String::new();
+        loop {
+            match self.peek() {
+                None | Some('\n') => return Err(unterminated(self)),
+                Some('"') => {
+                    self.bump();
+                    return Ok(string);
+                }
+                Some('\\') => string.push(self.escape()?),
+                Some(c) => {
+                    self.bump();
+                    string.push(c);
+                }
+            }
+        }
+    }
+
+    /// A backslash escape in a string, including `\uXXXX`.
+    fn escape(&mut self) -> Result<char, ParseError> {
+        let pos = (self.line, self.column);
+        let start = self.pos;
+        self.bump(); // \
+        let invalid = |parser: &Self| {
+            let token: String = parser.chars[start..parser.pos].iter().collect();
+            parser.error(ParseErrorKind::InvalidEscape, pos, &token)
+        };
+        let c = match self.bump() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => {
+                let high = self.hex4().ok_or_else(|| invalid(self))?;
+                let code = if (0xd800..0xdc00).contains(&high) {
+                    // A surrogate pair, for characters outside the BMP.
+                    let is_low = |c| (0xdc00..0xe000).contains(&c);
+                    if !(self.bump() == Some('\\') && self.bump() == Some('u')) {
+                        return Err(invalid(self));
+                    }
+                    let low = self.hex4().filter(|&c| is_low(c));
+                    let low = low.ok_or_else(|| invalid(self))?;
+                    0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+                } else {
+                    high
+                };
+                char::from_u32(code).ok_or_else(|| invalid(self))?
+            }
+            _ => return Err(invalid(self)),
+        };
+        Ok(c)
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        let mut code = 0;
+        for _ in 0..4 {
+            code = code * 16 + self.peek()?.to_digit(16)?;
+            self.bump();
+        }
+        Some(code)
+    }
+
+    fn number(&mut self) -> Result<f64, ParseError> {
+        let pos = (self.line, self.column);
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_digit() || "+-.eE".contains(c))
+        {
+            self.bump();
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        match text.parse() {
+            Ok(n) if is_json_number(&text) && f64::is_finite(n) => Ok(n),
+            _ => Err(self.error(ParseErrorKind::InvalidValue, pos, &text)),
+        }
+    }
+}
+
+/// Whether `text` matches JSON's number syntax, which is stricter than
+/// Rust's: no leading zeros, and digits on both sides of a `.`.
+fn is_json_number(text: &str) -> bool {
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let text = text.strip_prefix('-').unwrap_or(text);
+    let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+        Some((m, e)) => (m, Some(e.strip_prefix(['+', '-']).unwrap_or(e))),
+        None => (text, None),
+    };
+    let (int, frac) = match mantissa.split_once('.') {
+        Some((i, f)) => (i, Some(f)),
+        None => (mantissa, None),
+    };
+    digits(int)
+        && (int == "0" || !int.starts_with('0'))
+        && frac.is_none_or(digits)
+        && exponent.is_none_or(digits)
+}
+
+/// Turns parsed JSON into nodes.
+struct Converter<'a> {
+    text: &'a str,
+    file: Option<&'a Rc<str>>,
+}
+
+impl Converter<'_> {
+    fn error(&self, kind: ParseErrorKind, pos: (usize, usize), token: &str) -> ParseError {
+        ParseError::at(kind, pos, token, self.text)
+    }
+
+    fn root(&self, json: &Json) -> Result<Node, ParseError> {
+        let JsonValue::Object(entries) = &json.value else {
+            let first = self.text.trim_start().chars().next().unwrap_or_default();
+            let kind = ParseErrorKind::RootNotObject;
+            return Err(self.error(kind, json.pos, &first.to_string()));
+        };
+        Ok(self.node("scene", json.pos, vec![], self.children(entries)?))
+    }
+
+    fn node(
+        &self,
+        name: &str,
+        pos: (usize, usize),
+        values: Vec<Value>,
+        children: Vec<Node>,
+    ) -> Node {
+        Node {
+            name: name.to_owned(),
+            values,
+            children,
+            comments: vec![],
+            span: Span {
+                file: self.file.cloned(),
+                line: pos.0,
+                column: pos.1,
+            },
+            path: String::new(),
+        }
+    }
+
+    fn children(&self, entries: &[(Key, Json)]) -> Result<Vec<Node>, ParseError> {
+        let mut children = Vec::new();
+        for (key, json) in entries {
+            children.extend(self.nodes(key, json)?);
+        }
+        Ok(children)
+    }
+
+    /// The nodes for one key, usually just one.
+    fn nodes(&self, key: &Key, json: &Json) -> Result<Vec<Node>, ParseError> {
+        if !is_valid_name(&key.name) {
+            return Err(self.error(ParseErrorKind::InvalidName, key.pos, &key.name));
+        }
+        let block = |entries: &[(Key, Json)]| -> Result<Node, ParseError> {
+            Ok(self.node(&key.name, key.pos, vec![], self.children(entries)?))
+        };
+
+        match &json.value {
+            JsonValue::Object(entries) => Ok(vec![block(entries)?]),
+            JsonValue::Array(items) if !items.is_empty() => {
+                let objects: Vec<_> = items
+                    .iter()
+                    .filter_map(|item| match &item.value {
+                        JsonValue::Object(entries) => Some(entries.as_slice()),
+                        _ => None,
+                    })
+                    .collect();
+                if objects.len() == items.len() {
+                    return objects.into_iter().map(block).collect();
+                }
+                let values = items
+                    .iter()
+                    .map(|item| self.value(item, json.pos))
+                    .collect::<Result<_, _>>()?;
+                Ok(vec![self.node(&key.name, key.pos, values, vec![])])
+            }
+            _ => {
+                let values = match &json.value {
+                    JsonValue::Array(_) => vec![],
+                    _ => vec![self.value(json, json.pos)?],
+                };
+                Ok(vec![self.node(&key.name, key.pos, values, vec![])])
+            }
+        }
+    }
+
+    /// A scalar. `array` is where the enclosing array starts, if there is
+    /// one, for reporting a nested array or object.
+    fn value(&self, json: &Json, array: (usize, usize)) -> Result<Value, ParseError> {
+        match &json.value {
+            JsonValue::Null => Err(self.error(ParseErrorKind::Null, json.pos, "null")),
+            JsonValue::Bool(b) => Ok(Value::Bool(*b)),
+            JsonValue::Number(n) => Ok(Value::Number(*n)),
+            JsonValue::String(s) => Ok(Value::String(s.clone())),
+            JsonValue::Array(_) | JsonValue::Object(_) => {
+                Err(self.error(ParseErrorKind::MixedArray, array, "["))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_err(text: &str) -> ParseError {
+        parse_json(text, None).expect_err("expected a parse error")
+    }
+
+    #[test]
+    fn same_tree_as_sdl() {
+        let json = parse_json(
+            r#"{
+    "camera": { "position": [0, 0, -20.5] },
+    "focal-distance": 10,
+    "materials": { "wall": { "color": [255, 255, 255] } },
+    "objects": {
+        "sphere": [
+            { "center": [-5, 2.5e0, -2], "radius": 10 },
+            { "center": [5, -2.5, 5], "radius": 15, "material": "wall" }
+        ],
+        "plane": { "normal": [0, 0, 1], "anchor": [0, 0, 10] },
+        "plane": { "normal": [1, 0, 0], "anchor": [-30, 0, 0] }
+    },
+    "file": "te\"a\\pot\u00e9.obj",
+    "include": "walls",
+    "shadows": false,
+    "empty": []
+}"#,
+            None,
+        )
+        .unwrap();
+        let sdl: Node = r#"scene {
+    camera {
+        position 0 0 -20.5
+    }
+    focal-distance 10
+    materials {
+        wall {
+            color 255 255 255
+        }
+    }
+    objects {
+        sphere {
+            center -5 2.5 -2
+            radius 10
+        }
+        sphere {
+            center 5 -2.5 5
+            radius 15
+            material "wall"
+        }
+        plane {
+            normal 0 0 1
+            anchor 0 0 10
+        }
+        plane {
+            normal 1 0 0
+            anchor -30 0 0
+        }
+    }
+    file "te\"a\\poté.obj"
+    include "walls"
+    shadows false
+    empty
+}"#
+        .parse()
+        .unwrap();
+        assert_eq!(json.to_string(), sdl.to_string());
+
+        let plane = &json.get_path("objects").unwrap().children[3];
+        assert_eq!(plane.path, "scene > objects > plane[1]");
+        assert_eq!((plane.span.line, plane.span.column), (11, 10));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let cases = [
+            ("", ParseErrorKind::Empty, (1, 1), ""),
+            (
+                "{\n  \"a\": 1,\n}",
+                ParseErrorKind::UnexpectedToken,
+                (3, 1),
+                "}",
+            ),
+            (
+                "{\"a\": [1, 2}",
+                ParseErrorKind::UnexpectedToken,
+                (1, 12),
+                "}",
+            ),
+            (
+                "{\"a\": 1",
+                ParseErrorKind::UnexpectedEndOfInput,
+                (1, 8),
+                "",
+            ),
+            (
+                "{\"a\": 1} 2",
+                ParseErrorKind::UnexpectedToken,
+                (1, 10),
+                "2",
+            ),
+            (
+                "{\"a\": nil}",
+                ParseErrorKind::UnexpectedToken,
+                (1, 7),
+                "nil",
+            ),
+            ("{\"a\": 01}", ParseErrorKind::InvalidValue, (1, 7), "01"),
+            ("{\"a\": 1.}", ParseErrorKind::InvalidValue, (1, 7), "1."),
+            (
+                "{\"a\": 1e999}",
+                ParseErrorKind::InvalidValue,
+                (1, 7),
+                "1e999",
+            ),
+            (
+                "{\"a\": \"b\n}",
+                ParseErrorKind::UnterminatedString,
+                (1, 7),
+                "\"b",
+            ),
+            (
+                "{\"a\": \"\\x\"}",
+                ParseErrorKind::InvalidEscape,
+                (1, 8),
+                "\\x",
+            ),
+            (
+                "{\"a\": \"\\ud800\"}",
+                ParseErrorKind::InvalidEscape,
+                (1, 8),
+                "\\ud800\"",
+            ),
+        ];
+        for (text, kind, pos, token) in cases {
+            let e = parse_err(text);
+            assert_eq!(
+                (e.kind, (e.line, e.column), e.token.as_str()),
+                (kind, pos, token),
+                "{text}"
+            );
+        }
+        let tree = parse_json(r#"{"a": "\ud83d\ude00"}"#, None).unwrap();
+        let a = &tree.get_path("a").unwrap().values;
+        assert_eq!(a, &[Value::String("\u{1f600}".to_owned())]);
+    }
+
+    #[test]
+    fn too_deep() {
+        let deep = format!("{{\"a\": {}", "[".repeat(200_000));
+        let e = parse_err(&deep);
+        let column = 7 + MAX_NESTING - 1;
+        assert_eq!(
+            (e.kind, (e.line, e.column), e.token.as_str()),
+            (ParseErrorKind::TooDeep, (1, column), "[")
+        );
+    }
+
+    #[test]
+    fn conversion_errors() {
+        let cases = [
+            ("[1]", ParseErrorKind::RootNotObject, (1, 1), "["),
+            (
+                "{\n  \"3d\": 1\n}",
+                ParseErrorKind::InvalidName,
+                (2, 4),
+                "3d",
+            ),
+            ("{\"a\": null}", ParseErrorKind::Null, (1, 7), "null"),
+            ("{\"a\": [1, {}]}", ParseErrorKind::MixedArray, (1, 7), "["),
+            ("{\"a\": [[1]]}", ParseErrorKind::MixedArray, (1, 7), "["),
+        ];
+        for (text, kind, pos, token) in cases {
+            let e = parse_err(text);
+            assert_eq!(
+                (e.kind, (e.line, e.column), e.token.as_str()),
+                (kind, pos, token),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn error_names_file() {
+        let file: Rc<str> = "scene.json".into();
+        let e = parse_json("{\n  \"a\": [1,, 2]\n}", Some(&file)).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "unexpected \",\" at line 2, column 11 of scene.json
+  |
+2 |   \"a\": [1,, 2]
+  |           ^"
+        );
+    }
+}
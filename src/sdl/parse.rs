@@ -1,87 +1,697 @@
-use std::result::Result as StdResult;
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::rc::Rc;
 use std::str::FromStr;
 
-use crate::error::{Error, Result};
-
-use super::Node;
+use super::{expr, Node, Span, Value};
 
 impl FromStr for Node {
-    type Err = Error;
+    type Err = ParseError;
 
-    fn from_str(s: &str) -> Result<Self> {
+    fn from_str(s: &str) -> Result<Self, ParseError> {
         parse(s)
     }
 }
 
-// todo: handle errors without crashing
-// todo todo: provide helpful error messages
-fn parse(text: &str) -> Result<Node> {
-    let mut parents = Vec::new();
+/// A syntax error in an SDL file.
+///
+/// Carries enough context to point at the offending token in the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// 1-based.
+    pub line: usize,
+    /// 1-based, counted in chars.
+    pub column: usize,
+    /// The offending token. May be empty, e.g. at end of input.
+    pub token: String,
+    /// The full source line containing the error.
+    snippet: String,
+    /// The file the error is in, if it was read from one.
+    pub file: Option<Rc<str>>,
+}
 
-    // Dummy node, to store top-level nodes.
-    parents.push(Node {
-        name: "(not a real node)".to_owned(),
-        values: vec![],
-        children: vec![],
-    });
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    InvalidName,
+    /// A bare word that isn't a number, boolean or identifier.
+    InvalidValue,
+    UnterminatedString,
+    InvalidEscape,
+    /// A `{` somewhere other than the end of a line.
+    UnexpectedOpenBrace,
+    /// A `}` with no matching `{`.
+    UnmatchedCloseBrace,
+    /// Anything following a `}` on the same line.
+    TrailingTokens,
+    /// End of input inside a block. Points at the block's opening line.
+    UnclosedBlock,
+    MultipleTopLevelNodes,
+    Empty,
+    /// A `let` line not of the form `let NAME = EXPRESSION`.
+    InvalidLet,
+    /// A `let` or `repeat` variable named after a keyword, e.g. `on`.
+    ReservedName,
+    InvalidExpression,
+    /// A name with a `-` in it that could be a subtraction, e.g. `r-1`
+    /// when there's a variable `r`.
+    AmbiguousName,
+    UndefinedVariable,
+    UnknownFunction,
+    WrongArgumentCount,
+    /// E.g. division by zero.
+    NonFiniteResult,
+    /// A `repeat` line not of the form `repeat NAME START END [STEP] {`.
+    InvalidRepeat,
+    /// Loading generated more than 100,000 nodes.
+    TooManyNodes,
+    /// Blocks, arrays, objects or parentheses nested more than 128 deep.
+    TooDeep,
+    /// JSON syntax that's out of place, e.g. a missing comma.
+    UnexpectedToken,
+    UnexpectedEndOfInput,
+    /// A JSON `null`, which has no SDL equivalent.
+    Null,
+    /// A JSON array that doesn't hold only values or only objects.
+    MixedArray,
+    /// A JSON document that isn't an object.
+    RootNotObject,
+}
 
-    for mut line in text.lines() {
-        // Trim comments.
-        if let Some(idx) = line.find("//") {
-            line = &line[..idx];
+impl ParseError {
+    fn new(kind: ParseErrorKind, word: &Word, snippet: &str) -> Self {
+        Self {
+            kind,
+            line: word.line,
+            column: word.column,
+            token: word.text.to_owned(),
+            snippet: snippet.to_owned(),
+            file: None,
         }
+    }
 
-        let mut words: Vec<_> = line.split_whitespace().collect();
-        if words.is_empty() {
-            continue; // ignore blank lines
+    /// An error that doesn't come from a word in an SDL file, e.g. one in a
+    /// JSON file. `text` is the whole source.
+    pub(super) fn at(
+        kind: ParseErrorKind,
+        (line, column): (usize, usize),
+        token: &str,
+        text: &str,
+    ) -> Self {
+        Self {
+            kind,
+            line,
+            column,
+            token: token.to_owned(),
+            snippet: text.lines().nth(line - 1).unwrap_or_default().to_owned(),
+            file: None,
         }
-        if words[0] == "}" {
-            // close this context
-            let curr = parents.pop().unwrap();
-            let containing = parents.last_mut().unwrap();
-            containing.children.push(curr);
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            Self::InvalidName => "invalid node name",
+            Self::InvalidValue => "invalid value",
+            Self::UnterminatedString => "unterminated string",
+            Self::InvalidEscape => "invalid escape sequence",
+            Self::UnexpectedOpenBrace => "`{` must be the last token on its line",
+            Self::UnmatchedCloseBrace => "unmatched `}`",
+            Self::TrailingTokens => "unexpected token after `}`",
+            Self::UnclosedBlock => "block is never closed",
+            Self::MultipleTopLevelNodes => "expected exactly one top-level node, found another",
+            Self::Empty => "expected a top-level node, found nothing",
+            Self::InvalidLet => "expected `let NAME = EXPRESSION`, found",
+            Self::ReservedName => "a variable can't be named after the keyword",
+            Self::InvalidExpression => "invalid expression",
+            Self::AmbiguousName => {
+                "ambiguous name; quote it, or wrap it in parentheses if it's an expression:"
+            }
+            Self::UndefinedVariable => "undefined variable",
+            Self::UnknownFunction => "unknown function",
+            Self::WrongArgumentCount => "wrong number of arguments to",
+            Self::NonFiniteResult => "expression doesn't evaluate to a finite number:",
+            Self::InvalidRepeat => "expected `repeat NAME START END [STEP] {`, found",
+            Self::TooManyNodes => "too many nodes generated by",
+            Self::TooDeep => "nested too deeply:",
+            Self::UnexpectedToken => "unexpected",
+            Self::UnexpectedEndOfInput => "unexpected end of input",
+            Self::Null => "null isn't allowed here:",
+            Self::MixedArray => "expected an array of only values or only objects, found",
+            Self::RootNotObject => "expected an object at the top level, found",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if !self.token.is_empty() {
+            write!(f, " {:?}", self.token)?;
+        }
+        write!(f, " at line {}, column {}", self.line, self.column)?;
+        if let Some(file) = &self.file {
+            write!(f, " of {file}")?;
+        }
+
+        if !self.snippet.is_empty() {
+            // Keep tabs as tabs, so the caret lines up with the source.
+            let indent: String = self
+                .snippet
+                .chars()
+                .take(self.column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let carets = "^".repeat(self.token.chars().count().max(1));
+            let gutter = " ".repeat(self.line.to_string().len());
+
+            writeln!(f)?;
+            writeln!(f, "{gutter} |")?;
+            writeln!(f, "{} | {}", self.line, self.snippet)?;
+            write!(f, "{gutter} | {indent}{carets}")?;
+        }
+        Ok(())
+    }
+}
+
+impl StdError for ParseError {}
+
+/// A whitespace-separated token, with its position in the source.
+pub(super) struct Word<'a> {
+    /// As written in the source, including any quotes.
+    pub(super) text: &'a str,
+    line: usize,
+    column: usize,
+    /// The contents of a quoted string, with escapes resolved.
+    string: Option<String>,
+}
+
+impl Word<'_> {
+    fn is(&self, text: &str) -> bool {
+        self.string.is_none() && self.text == text
+    }
+}
+
+/// Splits a line into words, and any trailing comment.
+///
+/// A quoted string is one word, even if it contains whitespace or `//`.
+fn words(line: &str, line_number: usize) -> Result<(Vec<Word<'_>>, Option<&str>), ParseError> {
+    let chars: Vec<(usize, char)> = line.char_indices().collect();
+    let byte_offset = |i: usize| chars.get(i).map_or(line.len(), |&(idx, _)| idx);
+    let is_comment = |i: usize| line[byte_offset(i)..].starts_with("//");
+    let word = |start: usize, end: usize, string| Word {
+        text: &line[byte_offset(start)..byte_offset(end)],
+        line: line_number,
+        column: start + 1,
+        string,
+    };
+
+    let mut words = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i].1;
+        if c.is_whitespace() {
+            i += 1;
+        } else if is_comment(i) {
+            return Ok((words, Some(line[byte_offset(i)..].trim_end())));
+        } else if c == '"' {
+            let start = i;
+            let mut string = String::new();
+            i += 1;
+            loop {
+                let Some(&(_, c)) = chars.get(i) else {
+                    let w = word(start, chars.len(), None);
+                    return Err(ParseError::new(
+                        ParseErrorKind::UnterminatedString,
+                        &w,
+                        line,
+                    ));
+                };
+                i += 1;
+                match c {
+                    '"' => break,
+                    '\\' => {
+                        let escaped = match chars.get(i).map(|&(_, c)| c) {
+                            Some('"') => '"',
+                            Some('\\') => '\\',
+                            Some('n') => '\n',
+                            Some('t') => '\t',
+                            _ => {
+                                let w = word(i - 1, (i + 1).min(chars.len()), None);
+                                return Err(ParseError::new(
+                                    ParseErrorKind::InvalidEscape,
+                                    &w,
+                                    line,
+                                ));
+                            }
+                        };
+                        string.push(escaped);
+                        i += 1;
+                    }
+                    c => string.push(c),
+                }
+            }
+            words.push(word(start, i, Some(string)));
+        } else {
+            // Whitespace in parentheses doesn't end the word, e.g. `(2 * r)`.
+            let start = i;
+            let mut depth = 0;
+            while i < chars.len() {
+                let c = chars[i].1;
+                if depth == 0 && (c.is_whitespace() || is_comment(i)) {
+                    break;
+                }
+                match c {
+                    '(' => depth += 1,
+                    ')' if depth > 0 => depth -= 1,
+                    _ => (),
+                }
+                i += 1;
+            }
+            words.push(word(start, i, None));
+        }
+    }
+    Ok((words, None))
+}
+
+fn parse(text: &str) -> Result<Node, ParseError> {
+    parse_root(text, None)
+}
+
+/// Parses a whole scene file, which must have exactly one top-level node.
+pub fn parse_root(text: &str, file: Option<&Rc<str>>) -> Result<Node, ParseError> {
+    let nodes = parse_nodes(text, file, MAX_GENERATED_NODES)?;
+    let mut nodes = nodes.into_iter();
+    let Some(mut root) = nodes.next() else {
+        return Err(ParseError {
+            kind: ParseErrorKind::Empty,
+            line: 1,
+            column: 1,
+            token: String::new(),
+            snippet: String::new(),
+            file: file.cloned(),
+        });
+    };
+
+    // there should be exactly one top-level node in the file
+    if let Some(extra) = nodes.next() {
+        let snippet = text.lines().nth(extra.span.line - 1).unwrap_or_default();
+        return Err(ParseError {
+            kind: ParseErrorKind::MultipleTopLevelNodes,
+            line: extra.span.line,
+            column: extra.span.column,
+            token: extra.name,
+            snippet: snippet.to_owned(),
+            file: file.cloned(),
+        });
+    }
+
+    root.assign_paths(root.name.clone());
+    Ok(root)
+}
+
+/// Parses any number of top-level nodes, e.g. the contents of an included
+/// file. Their paths are left unset.
+///
+/// `file` is recorded in the nodes' spans, and in any error. Loops may
+/// generate at most `limit` nodes.
+pub fn parse_nodes(
+    text: &str,
+    file: Option<&Rc<str>>,
+    limit: usize,
+) -> Result<Vec<Node>, ParseError> {
+    let lines: Vec<&str> = text.lines().collect();
+    let in_file = |e| ParseError {
+        file: file.cloned(),
+        ..e
+    };
+    let (raw, _) = parse_raw(&lines).map_err(in_file)?;
+
+    let mut evaluator = Evaluator {
+        lines: &lines,
+        file,
+        scopes: vec![],
+        generated: 0,
+        limit,
+    };
+    evaluator.nodes(&raw).map_err(in_file)
+}
+
+/// A node before its values have been evaluated, as written in the source.
+pub(super) struct RawNode<'a> {
+    pub(super) name: Word<'a>,
+    pub(super) values: Vec<Word<'a>>,
+    pub(super) children: Vec<RawNode<'a>>,
+    /// Whether the node has a block, even an empty one.
+    pub(super) block: bool,
+    /// Comment lines directly above the node. An empty entry stands for a
+    /// blank line.
+    pub(super) comments: Vec<&'a str>,
+    /// A comment on the same line as the node.
+    pub(super) trailing: Option<&'a str>,
+    /// Comment lines at the end of the node's block.
+    pub(super) closing: Vec<&'a str>,
+}
+
+/// Parses the structure of a file without evaluating anything, keeping
+/// comments. Also returns any comments after the last node.
+pub(super) fn parse_verbatim(text: &str) -> Result<(Vec<RawNode<'_>>, Vec<&str>), ParseError> {
+    let lines: Vec<&str> = text.lines().collect();
+    parse_raw(&lines)
+}
+
+/// Works out the structure of the file: which lines are nodes, and which
+/// blocks they belong to.
+fn parse_raw<'a>(lines: &[&'a str]) -> Result<(Vec<RawNode<'a>>, Vec<&'a str>), ParseError> {
+    let error = |kind, word: &Word| ParseError::new(kind, word, lines[word.line - 1]);
+
+    // Blocks that haven't been closed yet.
+    let mut parents: Vec<RawNode> = Vec::new();
+    let mut top_level = Vec::new();
+    // Comments that haven't been attached to a node yet.
+    let mut comments = Vec::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        let (mut words, comment) = words(line, i + 1)?;
+        if words.is_empty() {
+            match comment {
+                Some(comment) => comments.push(comment),
+                // Keep blank lines between comments and nodes, but only one.
+                None if comments.last().is_some_and(|c: &&str| c.is_empty()) => (),
+                None => comments.push(""),
+            }
             continue;
         }
 
-        if !is_valid_name(words[0]) {
-            Err(format!("invalid node name: {}", words[0]))?
+        let siblings = match parents.last() {
+            Some(parent) => &parent.children,
+            None => &top_level,
+        };
+        if siblings.is_empty() {
+            // No blank lines at the start of a block.
+            let first = comments.iter().position(|c| !c.is_empty());
+            comments.drain(..first.unwrap_or(comments.len()));
         }
-        let name = words[0].to_owned();
 
-        let has_children = words.len() > 1 && *words.last().unwrap() == "{";
-        if has_children {
-            words.pop();
+        let node = if words[0].is("}") {
+            if let Some(extra) = words.get(1) {
+                return Err(error(ParseErrorKind::TrailingTokens, extra));
+            }
+            // close this context
+            let Some(mut node) = parents.pop() else {
+                return Err(error(ParseErrorKind::UnmatchedCloseBrace, &words[0]));
+            };
+            node.closing = trim_blank_lines(std::mem::take(&mut comments));
+            node.closing.extend(comment);
+            node
+        } else {
+            let has_children = words.len() > 1 && words.last().unwrap().is("{");
+            if has_children {
+                words.pop();
+            }
+
+            let name = words.remove(0);
+            if name.string.is_some() || !is_valid_name(name.text) {
+                return Err(error(ParseErrorKind::InvalidName, &name));
+            }
+            if let Some(brace) = words.iter().find(|w| w.is("{")) {
+                return Err(error(ParseErrorKind::UnexpectedOpenBrace, brace));
+            }
+            if has_children && name.is("let") {
+                return Err(error(ParseErrorKind::InvalidLet, &name));
+            }
+            if !has_children && name.is("repeat") {
+                return Err(error(ParseErrorKind::InvalidRepeat, &name));
+            }
+
+            let node = RawNode {
+                name,
+                values: words,
+                children: vec![],
+                block: has_children,
+                comments: std::mem::take(&mut comments),
+                trailing: comment,
+                closing: vec![],
+            };
+            if has_children {
+                if parents.len() >= MAX_NESTING {
+                    return Err(error(ParseErrorKind::TooDeep, &node.name));
+                }
+                // enter a new context
+                parents.push(node);
+                continue;
+            }
+            node
+        };
+
+        match parents.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => top_level.push(node),
         }
+    }
+
+    if let Some(node) = parents.first() {
+        // Report the outermost unclosed block; inner ones may be fine.
+        return Err(error(ParseErrorKind::UnclosedBlock, &node.name));
+    }
+
+    Ok((top_level, trim_blank_lines(comments)))
+}
+
+/// Removes blank lines from the start and end of a run of comments.
+fn trim_blank_lines(mut comments: Vec<&str>) -> Vec<&str> {
+    while comments.last().is_some_and(|c| c.is_empty()) {
+        comments.pop();
+    }
+    let first = comments.iter().position(|c| !c.is_empty());
+    comments.drain(..first.unwrap_or(comments.len()));
+    comments
+}
+
+/// Turns raw nodes into real ones, evaluating `let` bindings and
+/// expressions along the way.
+struct Evaluator<'a> {
+    lines: &'a [&'a str],
+    file: Option<&'a Rc<str>>,
+    /// Variables bound by `let` or `repeat`, innermost block last.
+    scopes: Vec<HashMap<&'a str, f64>>,
+    /// How many nodes and loop iterations have been evaluated so far.
+    generated: usize,
+    /// What's left of [`MAX_GENERATED_NODES`] for this file.
+    limit: usize,
+}
+
+/// Words that mean something else as values, so can't name variables.
+const KEYWORDS: [&str; 4] = ["true", "on", "false", "off"];
 
-        let values = words[1..]
+/// A limit on the size of a loaded tree, to catch runaway files. Nodes made
+/// by loops, included files and `use` all count towards it.
+pub(super) const MAX_GENERATED_NODES: usize = 100_000;
+
+/// How deeply blocks, JSON arrays and objects, and parentheses may nest.
+/// Everything that walks a tree or an expression recurses, so this keeps
+/// a hostile file from overflowing the stack.
+pub(super) const MAX_NESTING: usize = 128;
+
+impl<'a> Evaluator<'a> {
+    fn error(&self, kind: ParseErrorKind, word: &Word) -> ParseError {
+        ParseError::new(kind, word, self.lines[word.line - 1])
+    }
+
+    fn lookup(&self, name: &str) -> Option<f64> {
+        self.scopes
             .iter()
-            .map(|s| s.parse())
-            .collect::<StdResult<_, _>>()?;
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+    }
 
-        let node = Node {
-            name,
-            values,
-            children: vec![],
+    /// Evaluates the contents of one block, which gets its own scope.
+    fn nodes(&mut self, raw: &[RawNode<'a>]) -> Result<Vec<Node>, ParseError> {
+        self.scopes.push(HashMap::new());
+        let mut nodes = Vec::new();
+        for node in raw {
+            if node.name.is("let") {
+                self.let_binding(node)?;
+                continue;
+            }
+            if node.name.is("repeat") {
+                nodes.extend(self.repeat(node)?);
+                continue;
+            }
+
+            self.generated += 1;
+            let values = node
+                .values
+                .iter()
+                .map(|w| self.value(w))
+                .collect::<Result<_, _>>()?;
+            nodes.push(Node {
+                name: node.name.text.to_owned(),
+                values,
+                children: self.nodes(&node.children)?,
+                // A trailing comment moves above the node.
+                comments: (node.comments.iter().chain(&node.trailing))
+                    .map(|&c| c.to_owned())
+                    .collect(),
+                span: Span {
+                    file: self.file.cloned(),
+                    line: node.name.line,
+                    column: node.name.column,
+                },
+                path: String::new(),
+            });
+        }
+        self.scopes.pop();
+        Ok(nodes)
+    }
+
+    /// `repeat NAME START END [STEP] { ... }` evaluates its block once for each
+    /// value of NAME from START up to (but not including) END, and splices
+    /// the results into the enclosing block.
+    fn repeat(&mut self, node: &RawNode<'a>) -> Result<Vec<Node>, ParseError> {
+        let invalid = |word| Err(self.error(ParseErrorKind::InvalidRepeat, word));
+        let (name, bounds) = match node.values.split_first() {
+            Some((name, bounds)) if (2..=3).contains(&bounds.len()) => (name, bounds),
+            _ => return invalid(node.values.last().unwrap_or(&node.name)),
         };
+        if name.string.is_some() || !is_valid_name(name.text) {
+            return invalid(name);
+        }
+        self.check_not_keyword(name)?;
+        let mut numbers = [0., 0., 1.];
+        for (n, word) in numbers.iter_mut().zip(bounds) {
+            // The bounds can only be numbers, so a bare variable is one.
+            *n = match self.lookup(word.text).filter(|_| word.string.is_none()) {
+                Some(x) => x,
+                None => match self.value(word)? {
+                    Value::Number(x) => x,
+                    _ => return invalid(word),
+                },
+            };
+        }
+        let [start, end, step] = numbers;
+        if step == 0. {
+            return invalid(&bounds[2]);
+        }
 
-        if has_children {
-            // enter a new context
-            parents.push(node);
-        } else {
-            parents.last_mut().unwrap().children.push(node);
+        let iterations = ((end - start) / step).ceil().max(0.);
+        if iterations > self.limit as f64 {
+            return Err(self.error(ParseErrorKind::TooManyNodes, &node.name));
         }
+        let mut nodes = Vec::new();
+        for i in 0..iterations as u64 {
+            // Each iteration counts towards the limit, even if its block is
+            // empty, so that huge empty loops don't hang.
+            self.generated += 1;
+            if self.generated > self.limit {
+                return Err(self.error(ParseErrorKind::TooManyNodes, &node.name));
+            }
+            let value = start + i as f64 * step;
+            self.scopes.push(HashMap::from([(name.text, value)]));
+            let body = self.nodes(&node.children);
+            self.scopes.pop();
+            nodes.extend(body?);
+        }
+        Ok(nodes)
     }
 
-    // only the dummy context should be un-closed
-    assert_eq!(parents.len(), 1);
+    /// `let NAME = EXPRESSION`, where the expression may contain spaces.
+    fn let_binding(&mut self, node: &RawNode<'a>) -> Result<(), ParseError> {
+        let invalid = |word| Err(self.error(ParseErrorKind::InvalidLet, word));
+        let [name, equals, expr @ ..] = node.values.as_slice() else {
+            return invalid(node.values.last().unwrap_or(&node.name));
+        };
+        if name.string.is_some() || !is_valid_name(name.text) {
+            return invalid(name);
+        }
+        if !equals.is("=") {
+            return invalid(equals);
+        }
+        self.check_not_keyword(name)?;
+        let (Some(first), Some(last)) = (expr.first(), expr.last()) else {
+            return invalid(equals);
+        };
 
-    // there should be exactly one top-level node in the file
-    assert_eq!(parents[0].children.len(), 1);
+        // Evaluate the rest of the line as a single expression.
+        let line = self.lines[first.line - 1];
+        let byte_offset = |column| {
+            line.char_indices()
+                .nth(column - 1)
+                .map_or(line.len(), |(i, _)| i)
+        };
+        let start = byte_offset(first.column);
+        let end = byte_offset(last.column + last.text.chars().count());
+        let text = Word {
+            text: &line[start..end],
+            line: first.line,
+            column: first.column,
+            string: None,
+        };
+        let value = self.expression(&text)?;
+        self.scopes.last_mut().unwrap().insert(name.text, value);
+        Ok(())
+    }
 
-    Ok(parents.pop().unwrap().children.pop().unwrap())
+    fn check_not_keyword(&self, name: &Word) -> Result<(), ParseError> {
+        if KEYWORDS.contains(&name.text) {
+            return Err(self.error(ParseErrorKind::ReservedName, name));
+        }
+        Ok(())
+    }
+
+    /// Interprets a word as a value. Variables are only read in expressions,
+    /// so a bare name stays a name even if there's a variable called that.
+    fn value(&self, word: &Word) -> Result<Value, ParseError> {
+        if let Some(s) = &word.string {
+            return Ok(Value::String(s.clone()));
+        }
+        let text = word.text;
+        if text.starts_with('(') {
+            return Ok(Value::Number(self.expression(word)?));
+        }
+        let is_operator = |c: char| "+-*/%^(".contains(c);
+        let is_expression = text.chars().skip(1).any(is_operator);
+        // Checked first, so that e.g. `inf` is an identifier rather than a number.
+        if text.starts_with(|c: char| c.is_ascii_digit() || "+-.".contains(c)) {
+            // Rust reads e.g. `-inf` and `+nan` as numbers too, but like a
+            // bare `inf` they're names, here negated.
+            if let Some(n) = text.parse().ok().filter(|n: &f64| n.is_finite()) {
+                return Ok(Value::Number(n));
+            }
+            // E.g. `-room` or `2*r`.
+            if is_expression || text.contains(char::is_alphabetic) {
+                return Ok(Value::Number(self.expression(word)?));
+            }
+        }
+        match text {
+            "true" | "on" => Ok(Value::Bool(true)),
+            "false" | "off" => Ok(Value::Bool(false)),
+            // Names can contain `-`, so e.g. `a-b` is an identifier rather
+            // than a subtraction unless it's wrapped in parentheses. If a
+            // part of it is a variable, it's hard to say which was meant.
+            _ if text.contains('-') && text.split('-').any(|part| self.lookup(part).is_some()) => {
+                Err(self.error(ParseErrorKind::AmbiguousName, word))
+            }
+            _ if is_valid_name(text) => Ok(Value::Ident(text.to_owned())),
+            _ if is_expression => Ok(Value::Number(self.expression(word)?)),
+            _ => Err(self.error(ParseErrorKind::InvalidValue, word)),
+        }
+    }
+
+    fn expression(&self, word: &Word) -> Result<f64, ParseError> {
+        expr::eval(word.text, &|name| self.lookup(name)).map_err(|e| {
+            let mut err = self.error(e.kind, word);
+            err.column += e.offset;
+            err.token = e.token;
+            err
+        })
+    }
 }
 
-fn is_valid_name(name: &str) -> bool {
+pub(super) fn is_valid_name(name: &str) -> bool {
     let valid_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
     !name.starts_with(|c: char| c.is_ascii_digit()) && name.chars().all(valid_char)
 }
@@ -129,4 +739,357 @@ mod tests {
         // looks good to me
         dbg!(parse(s).unwrap());
     }
+
+    fn parse_err(s: &str) -> ParseError {
+        parse(s).expect_err("expected a parse error")
+    }
+
+    #[test]
+    fn invalid_value() {
+        let e = parse_err("scene {\n    radius 1.2.3\n}");
+        assert_eq!(e.kind, ParseErrorKind::InvalidValue);
+        assert_eq!((e.line, e.column), (2, 12));
+        assert_eq!(e.token, "1.2.3");
+    }
+
+    #[test]
+    fn invalid_name() {
+        let e = parse_err("scene {\n  3d-thing 1\n}");
+        assert_eq!(e.kind, ParseErrorKind::InvalidName);
+        assert_eq!((e.line, e.column), (2, 3));
+        assert_eq!(e.token, "3d-thing");
+    }
+
+    #[test]
+    fn unmatched_close_brace() {
+        let e = parse_err("scene {\n}\n}");
+        assert_eq!(e.kind, ParseErrorKind::UnmatchedCloseBrace);
+        assert_eq!((e.line, e.column), (3, 1));
+    }
+
+    #[test]
+    fn unclosed_block() {
+        let e = parse_err("scene {\n  camera {\n    position 0 0 0\n");
+        assert_eq!(e.kind, ParseErrorKind::UnclosedBlock);
+        assert_eq!((e.line, e.column), (1, 1));
+        assert_eq!(e.token, "scene");
+    }
+
+    #[test]
+    fn open_brace_mid_line() {
+        let e = parse_err("scene { 1 2\n}");
+        assert_eq!(e.kind, ParseErrorKind::UnexpectedOpenBrace);
+        assert_eq!((e.line, e.column), (1, 7));
+    }
+
+    #[test]
+    fn trailing_tokens() {
+        let e = parse_err("scene {\n} foo");
+        assert_eq!(e.kind, ParseErrorKind::TrailingTokens);
+        assert_eq!((e.line, e.column), (2, 3));
+        assert_eq!(e.token, "foo");
+    }
+
+    #[test]
+    fn multiple_top_level_nodes() {
+        let e = parse_err("scene {\n}\nother 1");
+        assert_eq!(e.kind, ParseErrorKind::MultipleTopLevelNodes);
+        assert_eq!((e.line, e.column), (3, 1));
+        assert_eq!(e.token, "other");
+    }
+
+    #[test]
+    fn empty() {
+        let e = parse_err("  // nothing here\n\n");
+        assert_eq!(e.kind, ParseErrorKind::Empty);
+    }
+
+    #[test]
+    fn display_underlines_token() {
+        let e = parse_err("scene {\n    radius 1.2.3\n}");
+        let expected = "\
+invalid value \"1.2.3\" at line 2, column 12
+  |
+2 |     radius 1.2.3
+  |            ^^^^^";
+        assert_eq!(e.to_string(), expected);
+    }
+
+    #[test]
+    fn value_kinds() {
+        let node = parse(r#"file "my teapot.obj" orthographic off true -2.5 inf"#).unwrap();
+        assert_eq!(
+            node.values,
+            [
+                Value::String("my teapot.obj".to_owned()),
+                Value::Ident("orthographic".to_owned()),
+                Value::Bool(false),
+                Value::Bool(true),
+                Value::Number(-2.5),
+                Value::Ident("inf".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn string_escapes() {
+        let node = parse(r#"s "a \"quoted\" // not a comment\\ \n\t" // a comment"#).unwrap();
+        let expected = "a \"quoted\" // not a comment\\ \n\t";
+        assert_eq!(node.values, [Value::String(expected.to_owned())]);
+    }
+
+    #[test]
+    fn unterminated_string() {
+        let e = parse_err("scene {\n    file \"teapot.obj\n}");
+        assert_eq!(e.kind, ParseErrorKind::UnterminatedString);
+        assert_eq!((e.line, e.column), (2, 10));
+        assert_eq!(e.token, "\"teapot.obj");
+    }
+
+    #[test]
+    fn invalid_escape() {
+        let e = parse_err(r#"file "C:\query.obj""#);
+        assert_eq!(e.kind, ParseErrorKind::InvalidEscape);
+        assert_eq!((e.line, e.column), (1, 9));
+        assert_eq!(e.token, r"\q");
+    }
+
+    #[test]
+    fn quoted_name() {
+        let e = parse_err(r#""scene" {"#);
+        assert_eq!(e.kind, ParseErrorKind::InvalidName);
+    }
+
+    #[test]
+    fn variables_and_expressions() {
+        let node = parse(
+            "scene {
+    let room = 30
+    let r = room / 10 - 1 // a comment
+    anchor (-room) 0 -room
+    radius 2*r+1 (r * 2) sqrt(r)
+    sphere {
+        let r = 1
+        radius (r)
+    }
+    after (r)
+    let x = 2  *  (r)
+    again (x)
+}",
+        )
+        .unwrap();
+        let numbers = |path| node.get_path(path).unwrap().values.clone();
+        let n = Value::Number;
+        assert!(node.get_path("let").is_err());
+        assert_eq!(numbers("anchor"), [n(-30.), n(0.), n(-30.)]);
+        assert_eq!(numbers("radius"), [n(5.), n(4.), n(2_f64.sqrt())]);
+        assert_eq!(numbers("sphere radius"), [n(1.)]);
+        // Bindings are scoped to their block.
+        assert_eq!(numbers("after"), [n(2.)]);
+        assert_eq!(numbers("again"), [n(4.)]);
+    }
+
+    #[test]
+    fn expression_errors() {
+        let e = parse_err(
+            "scene {
+    radius (2 * rr)
+}",
+        );
+        assert_eq!(e.kind, ParseErrorKind::UndefinedVariable);
+        assert_eq!((e.line, e.column, e.token.as_str()), (2, 17, "rr"));
+
+        let e = parse_err(
+            "scene {
+    radius 1/0
+}",
+        );
+        assert_eq!(e.kind, ParseErrorKind::NonFiniteResult);
+
+        let e = parse_err(
+            "scene {
+    sphere {
+        let r = 1
+    }
+    radius r*2
+}",
+        );
+        assert_eq!((e.kind, e.line), (ParseErrorKind::UndefinedVariable, 5));
+
+        // Non-finite numbers are never literals.
+        for (text, kind, token) in [
+            ("-inf", ParseErrorKind::UndefinedVariable, "inf"),
+            ("+NaN", ParseErrorKind::UndefinedVariable, "NaN"),
+            ("1e999", ParseErrorKind::NonFiniteResult, "1e999"),
+        ] {
+            let e = parse_err(&format!("scene {{\n    radius {text}\n}}"));
+            assert_eq!((e.kind, e.token.as_str()), (kind, token), "{text}");
+        }
+        let node = parse("scene {\n    let inf = 2\n    radius -inf\n}").unwrap();
+        assert_eq!(node.children[0].values, [Value::Number(-2.)]);
+
+        let e = parse_err("scene {\n    radius (1 + 2\n}");
+        assert_eq!(e.kind, ParseErrorKind::InvalidExpression);
+        assert!(
+            e.to_string()
+                .starts_with("invalid expression at line 2, column 18\n"),
+            "{e}"
+        );
+    }
+
+    #[test]
+    fn variables_and_names() {
+        // A variable is only read in an expression, so it doesn't replace a
+        // bare name, even if it's the same.
+        let node = parse(
+            "scene {
+    let red = 3
+    define red {
+    }
+    material red
+    use red
+    radius red
+    radius (red)
+    repeat i 0 red {
+        n (i)
+    }
+}",
+        )
+        .unwrap();
+        let red = Value::Ident("red".to_owned());
+        let values: Vec<_> = node.children.iter().map(|ch| &ch.values[0]).collect();
+        let n = Value::Number;
+        assert_eq!(
+            values,
+            [&red, &red, &red, &red, &n(3.), &n(0.), &n(1.), &n(2.)]
+        );
+
+        // `r-1` is a name, unless there's a variable `r`.
+        let node = parse("scene {\n    radius r-1\n}").unwrap();
+        assert_eq!(node.children[0].values, [Value::Ident("r-1".to_owned())]);
+        let e = parse_err("scene {\n    let r = 2\n    radius r-1\n}");
+        assert_eq!(
+            (e.kind, e.line, e.column, e.token.as_str()),
+            (ParseErrorKind::AmbiguousName, 3, 12, "r-1")
+        );
+        assert!(e.to_string().starts_with("ambiguous name;"), "{e}");
+        let node = parse("scene {\n    let r = 2\n    radius (r-1) \"r-1\"\n}").unwrap();
+        assert_eq!(
+            node.children[0].values,
+            [n(1.), Value::String("r-1".to_owned())]
+        );
+
+        for text in [
+            "let on = 1",
+            "repeat off 0 2 {
+}",
+        ] {
+            let e = parse_err(&format!(
+                "scene {{
+    {text}
+}}"
+            ));
+            assert_eq!(e.kind, ParseErrorKind::ReservedName);
+            assert!(
+                e.to_string()
+                    .starts_with("a variable can't be named after the keyword"),
+                "{e}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_let() {
+        for (text, token) in [
+            ("let = 1", "="),
+            ("let r 1", "1"),
+            ("let r =", "="),
+            ("let 2r = 1", "2r"),
+            (
+                "let r = 1 {
+}",
+                "let",
+            ),
+        ] {
+            let e = parse_err(&format!("scene {{\n{text}\n}}"));
+            assert_eq!(
+                (e.kind, e.token.as_str()),
+                (ParseErrorKind::InvalidLet, token)
+            );
+        }
+    }
+
+    #[test]
+    fn repeat() {
+        let node = parse(
+            "objects {
+    repeat i 0 3 {
+        repeat j 0 (i + 1) {
+            let x = i * 10 + j
+            sphere {
+                center (x) 0 0
+            }
+        }
+    }
+    repeat k 1 0 -0.5 {
+        plane {
+            anchor (k) 0 0
+        }
+    }
+    repeat k 0 0 {
+    }
+}",
+        )
+        .unwrap();
+        let x = |node: &Node| node.children[0].values[0].clone();
+        let xs: Vec<_> = node.children.iter().map(x).collect();
+        let n = Value::Number;
+        let expected = [0., 10., 11., 20., 21., 22., 1., 0.5].map(n);
+        assert_eq!(xs, expected);
+        assert_eq!(node.children[2].span.line, 5);
+    }
+
+    #[test]
+    fn invalid_repeat() {
+        for (text, token) in [
+            ("repeat i 0 3", "repeat"),
+            ("repeat i 0 {", "0"),
+            ("repeat i 0 1 2 3 {", "3"),
+            ("repeat \"i\" 0 3 {", "\"i\""),
+            ("repeat i 0 open {", "open"),
+            ("repeat i 0 3 0 {", "0"),
+        ] {
+            let e = parse_err(&format!("scene {{\n{text}\n}}\n}}"));
+            assert_eq!(
+                (e.kind, e.token.as_str()),
+                (ParseErrorKind::InvalidRepeat, token)
+            );
+        }
+    }
+
+    #[test]
+    fn too_many_nodes() {
+        let e = parse_err("scene {\n    repeat i 0 1e9 {\n    }\n}");
+        assert_eq!((e.kind, e.line), (ParseErrorKind::TooManyNodes, 2));
+
+        let e = parse_err(
+            "scene {
+    repeat i 0 1000 {
+        repeat j 0 200 {
+            x
+        }
+    }
+}",
+        );
+        assert_eq!((e.kind, e.line), (ParseErrorKind::TooManyNodes, 3));
+    }
+
+    #[test]
+    fn too_deep() {
+        let deep = "a {\n".repeat(MAX_NESTING + 1) + &"}\n".repeat(MAX_NESTING + 1);
+        let e = parse_err(&deep);
+        assert_eq!((e.kind, e.line), (ParseErrorKind::TooDeep, MAX_NESTING + 1));
+        let fine = "a {\n".repeat(MAX_NESTING) + &"}\n".repeat(MAX_NESTING);
+        assert!(parse(&fine).is_ok());
+    }
 }
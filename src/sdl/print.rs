@@ -0,0 +1,392 @@
+//! Writes SDL back out in canonical form: four-space indentation, one node
+//! per line, and the keys of each known block in the order its schema
+//! declares them.
+//!
+//! [`format`] works on source text, and keeps it as written: comments, `let`
+//! and `repeat` lines, includes and expressions. A [`Node`] tree has had all
+//! of those evaluated, so its `Display` writes only the result, along with
+//! any comments the nodes still carry.
+
+use std::fmt::{self, Write};
+
+use super::{
+    from_node::FromNode,
+    into_scene::SceneFile,
+    parse::{parse_verbatim, ParseError, RawNode},
+    registry::{self, ObjectRegistry},
+    schema::FieldType,
+    Node,
+};
+
+/// Formats SDL source text canonically.
+pub fn format(text: &str) -> Result<String, ParseError> {
+    let (nodes, comments) = parse_verbatim(text)?;
+    let comments: Vec<String> = comments.into_iter().map(str::to_owned).collect();
+    let mut lines: Vec<Line> = nodes.iter().map(Line::from).collect();
+    sort_top_level(&mut lines);
+
+    let mut out = String::new();
+    for line in &lines {
+        line.write(&mut out, 0).unwrap();
+    }
+    write_comments(&mut out, &comments, 0).unwrap();
+    Ok(out)
+}
+
+/// Writes the node and its descendants as SDL, which parses back to the same
+/// tree (give or take the order of keys).
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut lines = [Line::from(self)];
+        sort_top_level(&mut lines);
+        lines[0].write(f, 0)
+    }
+}
+
+/// One node, ready to be written.
+struct Line {
+    name: String,
+    values: Vec<String>,
+    comments: Vec<String>,
+    trailing: Option<String>,
+    /// `None` if the node has no block.
+    children: Option<Vec<Line>>,
+    /// Comments at the end of the block.
+    closing: Vec<String>,
+}
+
+impl From<&RawNode<'_>> for Line {
+    fn from(node: &RawNode) -> Self {
+        let owned = |comments: &[&str]| comments.iter().map(|&c| c.to_owned()).collect();
+        Self {
+            name: node.name.text.to_owned(),
+            values: node.values.iter().map(|w| w.text.to_owned()).collect(),
+            comments: owned(&node.comments),
+            trailing: node.trailing.map(str::to_owned),
+            children: node
+                .block
+                .then(|| node.children.iter().map(Self::from).collect()),
+            closing: owned(&node.closing),
+        }
+    }
+}
+
+impl From<&Node> for Line {
+    fn from(node: &Node) -> Self {
+        Self {
+            name: node.name.clone(),
+            values: node.values.iter().map(|v| v.to_string()).collect(),
+            comments: node.comments.clone(),
+            trailing: None,
+            children: (!node.children.is_empty())
+                .then(|| node.children.iter().map(Self::from).collect()),
+            closing: vec![],
+        }
+    }
+}
+
+impl Line {
+    fn write(&self, out: &mut dyn Write, depth: usize) -> fmt::Result {
+        write_comments(out, &self.comments, depth)?;
+        write!(out, "{}{}", indent(depth), self.name)?;
+        for value in &self.values {
+            write!(out, " {value}")?;
+        }
+        if self.children.is_some() {
+            write!(out, " {{")?;
+        }
+        if let Some(comment) = &self.trailing {
+            write!(out, " {comment}")?;
+        }
+        writeln!(out)?;
+
+        if let Some(children) = &self.children {
+            for child in children {
+                child.write(out, depth + 1)?;
+            }
+            write_comments(out, &self.closing, depth + 1)?;
+            writeln!(out, "{}}}", indent(depth))?;
+        }
+        Ok(())
+    }
+
+    /// Removes blank lines above the node, for when it's first in its block.
+    fn strip_blank_lines(&mut self) {
+        let first = self.comments.iter().position(|c| !c.is_empty());
+        self.comments.drain(..first.unwrap_or(self.comments.len()));
+    }
+}
+
+fn indent(depth: usize) -> String {
+    "    ".repeat(depth)
+}
+
+fn write_comments(out: &mut dyn Write, comments: &[String], depth: usize) -> fmt::Result {
+    for comment in comments {
+        if comment.is_empty() {
+            writeln!(out)?;
+        } else {
+            writeln!(out, "{}{comment}", indent(depth))?;
+        }
+    }
+    Ok(())
+}
+
+/// Top-level nodes keep their order; only a `scene` block has a schema.
+fn sort_top_level(lines: &mut [Line]) {
+    let registry = registry::registered();
+    for line in lines.iter_mut() {
+        let ty = (line.name == "scene").then_some(SceneFile::TYPE);
+        sort_children(line, ty, &registry);
+    }
+    if let Some(first) = lines.first_mut() {
+        first.strip_blank_lines();
+    }
+}
+
+/// Sorts the keys of `line`'s block by their position in its schema, and
+/// does the same for its descendants.
+///
+/// Lines that aren't keys in the schema, such as `let` and `materials`, stay
+/// where they are, and keys are never moved past them, since a key may
+/// depend on a `let` above it.
+fn sort_children(line: &mut Line, ty: Option<FieldType>, registry: &ObjectRegistry) {
+    let Some(children) = &mut line.children else {
+        return;
+    };
+
+    for child in children.iter_mut() {
+        let child_ty = match ty {
+            // The body of a loop is part of the enclosing block.
+            _ if child.name == "repeat" => ty,
+            Some(FieldType::Block(schema)) => schema
+                .fields
+                .iter()
+                .find(|f| f.name == child.name)
+                .map(|f| f.ty),
+            Some(FieldType::Objects) => registry.field_type(&child.name),
+            _ => None,
+        };
+        sort_children(child, child_ty, registry);
+    }
+
+    if let Some(FieldType::Block(schema)) = ty {
+        let position = |line: &Line| schema.fields.iter().position(|f| f.name == line.name);
+        for run in children.split_mut(|line| position(line).is_none()) {
+            run.sort_by_key(position);
+        }
+    }
+    if let Some(first) = children.first_mut() {
+        first.strip_blank_lines();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdl::{Span, Value};
+
+    fn assert_same_tree(a: &Node, b: &Node) {
+        assert_eq!(a.name, b.name);
+        assert_eq!(a.values, b.values, "values of {}", a.path);
+        assert_eq!(a.comments, b.comments, "comments of {}", a.path);
+        assert_eq!(a.children.len(), b.children.len(), "children of {}", a.path);
+        for (a, b) in a.children.iter().zip(&b.children) {
+            assert_same_tree(a, b);
+        }
+    }
+
+    #[test]
+    fn formats_source() {
+        let text = "
+// The scene.
+
+scene {
+
+
+  objects {
+    let r = 2*3     // radius
+    sphere {
+        radius r
+        center 0   (r + 1)   0
+        color 255 0 0 // red
+        // nothing else
+    }
+    repeat i 0 3 {
+      plane {
+anchor (i * 10) 0 0
+      }
+    }
+  }
+    camera {
+        right 1 0 0
+
+
+        position 0 0 -20
+    }
+    file \"a \\\"b\\\"\"
+}
+// The end.
+";
+        let expected = "// The scene.
+
+scene {
+    camera {
+        position 0 0 -20
+        right 1 0 0
+    }
+    objects {
+        let r = 2*3 // radius
+        sphere {
+            color 255 0 0 // red
+            center 0 (r + 1) 0
+            radius r
+            // nothing else
+        }
+        repeat i 0 3 {
+            plane {
+                anchor (i * 10) 0 0
+            }
+        }
+    }
+    file \"a \\\"b\\\"\"
+}
+// The end.
+";
+        assert_eq!(format(text).unwrap(), expected);
+        assert_eq!(format(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn formats_scene_file() {
+        let text = std::fs::read_to_string("scene.sdl").unwrap();
+        let formatted = format(&text).unwrap();
+        assert_eq!(format(&formatted).unwrap(), formatted);
+
+        let tree: Node = text.parse().unwrap();
+        let reparsed: Node = formatted.parse().unwrap();
+        assert_eq!(tree.to_string(), reparsed.to_string());
+    }
+
+    #[test]
+    fn displays_node() {
+        let tree: Node = "scene {
+    objects {
+        // A red sphere.
+        sphere {
+            radius 1.5 // big
+            center (-1) 0 1e3
+            color 255 0 0
+        }
+    }
+    projection orthographic
+    shadows off
+}"
+        .parse()
+        .unwrap();
+        assert_eq!(
+            tree.to_string(),
+            "scene {
+    objects {
+        // A red sphere.
+        sphere {
+            color 255 0 0
+            center -1 0 1000
+            // big
+            radius 1.5
+        }
+    }
+    projection orthographic
+    shadows false
+}
+"
+        );
+    }
+
+    /// A small xorshift generator, so the property test is reproducible.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            items[self.below(items.len())]
+        }
+    }
+
+    fn random_value(rng: &mut Rng) -> Value {
+        match rng.below(4) {
+            0 => {
+                let magnitude = 10_f64.powi(rng.below(40) as i32 - 20);
+                let x = (rng.next() as f64 / u64::MAX as f64 - 0.5) * magnitude;
+                Value::Number(if rng.below(2) == 0 { x.round() } else { x })
+            }
+            1 => {
+                let parts = ["a", " ", "\"", "\\", "\n", "\t", "//", "{", "}", "é", "("];
+                let len = rng.below(6);
+                Value::String((0..len).map(|_| rng.pick(&parts)).collect())
+            }
+            2 => Value::Ident(rng.pick(&["x", "glossy-white", "_a1", "inf", "a-b"]).into()),
+            _ => Value::Bool(rng.below(2) == 0),
+        }
+    }
+
+    fn random_node(rng: &mut Rng, depth: usize) -> Node {
+        let comments = match rng.below(4) {
+            0 => vec!["// a comment".to_owned()],
+            1 => vec!["".to_owned(), "//".to_owned(), "// {".to_owned()],
+            _ => vec![],
+        };
+        let children = if depth < 3 {
+            (0..rng.below(4))
+                .map(|_| random_node(rng, depth + 1))
+                .collect()
+        } else {
+            vec![]
+        };
+        Node {
+            name: rng.pick(&["a", "b-c", "d_e", "f1"]).to_owned(),
+            values: (0..rng.below(4)).map(|_| random_value(rng)).collect(),
+            children,
+            comments,
+            span: Span::default(),
+            path: String::new(),
+        }
+    }
+
+    #[test]
+    fn print_then_parse_is_identity() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..500 {
+            let mut tree = random_node(&mut rng, 0);
+            // Blank lines aren't kept at the start of the file or a block.
+            tree.comments.retain(|c| !c.is_empty());
+            strip_leading_blank_lines(&mut tree);
+
+            let text = tree.to_string();
+            let reparsed: Node = text.parse().unwrap_or_else(|e| panic!("{e}\n{text}"));
+            assert_same_tree(&tree, &reparsed);
+            assert_eq!(reparsed.to_string(), text);
+        }
+    }
+
+    fn strip_leading_blank_lines(node: &mut Node) {
+        if let Some(first) = node.children.first_mut() {
+            while first.comments.first().is_some_and(|c| c.is_empty()) {
+                first.comments.remove(0);
+            }
+        }
+        for child in &mut node.children {
+            strip_leading_blank_lines(child);
+        }
+    }
+}
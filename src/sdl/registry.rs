@@ -0,0 +1,156 @@
+use std::{
+    collections::BTreeMap,
+    sync::{LazyLock, RwLock},
+};
+
+use crate::objects::{Object, Plane, Sphere};
+
+use super::{
+    from_node::FromNode,
+    schema::{with_suggestion, FieldType},
+    Node, NodeError,
+};
+
+/// Builds an object from its SDL node.
+pub type ObjectParser = fn(&Node) -> Result<Box<dyn Object>, NodeError>;
+
+/// Maps SDL node names (e.g. `sphere`) to the parser for that kind of object,
+/// and how its block is validated.
+///
+/// New primitives become available in scene files with [`register_object`].
+#[derive(Clone)]
+pub struct ObjectRegistry {
+    entries: BTreeMap<&'static str, (ObjectParser, FieldType)>,
+}
+
+impl ObjectRegistry {
+    pub fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Replaces any type previously registered under `name`.
+    pub fn register<T: Object + FromNode>(&mut self, name: &'static str) {
+        self.entries.insert(name, (boxed::<T>, T::TYPE));
+    }
+
+    pub fn parse(&self, node: &Node) -> Result<Box<dyn Object>, NodeError> {
+        let Some((parser, _)) = self.entries.get(node.name.as_str()) else {
+            Err(self.unknown(node))?
+        };
+        parser(node)
+    }
+
+    pub fn field_type(&self, name: &str) -> Option<FieldType> {
+        self.entries.get(name).map(|&(_, ty)| ty)
+    }
+
+    /// The error for a node that isn't a registered object type.
+    pub fn unknown(&self, node: &Node) -> NodeError {
+        let message = format!("unknown object type {:?}", node.name);
+        let known = self.entries.keys().copied();
+        let suggested = with_suggestion(message.clone(), &node.name, known.clone());
+        if suggested != message {
+            return node.error(suggested);
+        }
+        let known: Vec<_> = known.collect();
+        node.error(format!("{message} (expected one of: {})", known.join(", ")))
+    }
+}
+
+impl Default for ObjectRegistry {
+    /// All the built-in primitives.
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register::<Sphere>("sphere");
+        registry.register::<Plane>("plane");
+        registry
+    }
+}
+
+fn boxed<T: Object + FromNode>(node: &Node) -> Result<Box<dyn Object>, NodeError> {
+    Ok(Box::new(T::from_node(node)?))
+}
+
+/// The object types that loading, validating and formatting all use.
+static REGISTERED: LazyLock<RwLock<ObjectRegistry>> = LazyLock::new(Default::default);
+
+/// Makes `T` available in scene files as blocks named `name` in `objects`,
+/// alongside the built-in `sphere` and `plane`. Replaces any type already
+/// registered under `name`.
+pub fn register_object<T: Object + FromNode>(name: &'static str) {
+    REGISTERED.write().unwrap().register::<T>(name);
+}
+
+/// A snapshot of the registered object types, so that no lock is held while
+/// they're used.
+pub(super) fn registered() -> ObjectRegistry {
+    REGISTERED.read().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sdl::format, Scene};
+
+    fn parse_tree(text: &str) -> Node {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn unknown_object_type() {
+        let tree = parse_tree("scene {\n objects {\n  cube {\n   radius 1\n  }\n }\n}");
+        let node = &tree.get_path("objects").unwrap().children[0];
+        let err = ObjectRegistry::default().parse(node).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "scene > objects > cube (line 3): \
+             unknown object type \"cube\" (expected one of: plane, sphere)"
+        );
+    }
+
+    #[test]
+    fn malformed_sphere_reports_sphere_error() {
+        let tree = parse_tree("scene {\n objects {\n  sphere {\n   radius 1\n  }\n }\n}");
+        let node = &tree.get_path("objects").unwrap().children[0];
+        let err = ObjectRegistry::default().parse(node).err().unwrap();
+        assert!(
+            err.to_string().contains("no child named \"color\""),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn custom_names() {
+        let tree = parse_tree(
+            "scene {\n objects {\n  ball {\n   color 1 2 3\n   center 0 0 0\n   radius 1\n  }\n }\n}",
+        );
+        let node = &tree.get_path("objects").unwrap().children[0];
+
+        let mut registry = ObjectRegistry::new();
+        assert!(registry.parse(node).is_err());
+        registry.register::<Sphere>("ball");
+        assert!(registry.parse(node).is_ok());
+    }
+
+    #[test]
+    fn registered_objects() {
+        let text = include_str!("../../scene.sdl").replace("sphere {", "globe {");
+        let err = Scene::from_sdl(&text).err().unwrap();
+        assert!(
+            err.to_string().contains("unknown object type \"globe\""),
+            "{err}"
+        );
+
+        register_object::<Sphere>("globe");
+        assert_eq!(Scene::from_sdl(&text).unwrap().objects().len(), 7);
+        // Its keys are sorted like a sphere's.
+        let formatted =
+            format(&text.replace("color 0 255 0", "radius 10\n color 0 255 0")).unwrap();
+        assert!(
+            formatted.contains("globe {\n            color 0 255 0\n"),
+            "{formatted}"
+        );
+    }
+}
@@ -0,0 +1,319 @@
+//! Declares which keys each kind of SDL node may contain, and checks a parsed
+//! tree against those declarations before it's converted into a scene.
+
+use std::{error::Error as StdError, fmt};
+
+use super::{from_node::FromNode, into_scene::SceneFile, registry, Node, NodeError, Value};
+
+/// The set of keys allowed inside one kind of block.
+#[derive(Debug)]
+pub struct Schema {
+    pub fields: &'static [Field],
+}
+
+#[derive(Debug)]
+pub struct Field {
+    pub name: &'static str,
+    pub ty: FieldType,
+    pub presence: Presence,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum FieldType {
+    /// A leaf node, checked by trying to convert it.
+    Value(fn(&Node) -> Result<(), NodeError>),
+    Block(&'static Schema),
+    /// A block whose children are objects, e.g. `sphere` or `plane`.
+    Objects,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Presence {
+    Required,
+    /// If missing, a node with these values is filled in.
+    Default(&'static [f64]),
+}
+
+/// Problems found while validating a tree.
+///
+/// Errors prevent the scene from being built; warnings don't.
+#[derive(Debug, Default)]
+pub(super) struct Report {
+    pub(super) errors: Vec<NodeError>,
+    pub(super) warnings: Vec<NodeError>,
+}
+
+/// Every error found in a tree, not just the first.
+#[derive(Debug)]
+pub struct ValidationError(pub Vec<NodeError>);
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, e) in self.0.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{e}")?;
+        }
+        Ok(())
+    }
+}
+
+impl StdError for ValidationError {}
+
+/// Checks a whole scene tree, filling in defaults for missing optional keys.
+///
+/// Returns any warnings (e.g. unknown keys) if there were no errors.
+pub fn validate(tree: &mut Node) -> Result<Vec<NodeError>, ValidationError> {
+    let mut report = Report::default();
+    if tree.name != "scene" {
+        let e = tree.error(format!(
+            "expected top-level node \"scene\", found {:?}",
+            tree.name
+        ));
+        report.errors.push(e);
+    }
+    SceneFile::TYPE.validate(tree, &mut report);
+
+    if report.errors.is_empty() {
+        Ok(report.warnings)
+    } else {
+        Err(ValidationError(report.errors))
+    }
+}
+
+impl Schema {
+    fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+
+    pub(super) fn validate(&self, node: &mut Node, report: &mut Report) {
+        if !node.values.is_empty() {
+            report.errors.push(node.error(format!(
+                "expected a block with no values, found {} values",
+                node.values.len()
+            )));
+        }
+
+        for (i, child) in node.children.iter().enumerate() {
+            if self.field(&child.name).is_none() {
+                let known = self.fields.iter().map(|f| f.name);
+                let message =
+                    with_suggestion(format!("unknown key {:?}", child.name), &child.name, known);
+                report.warnings.push(child.error(message));
+                continue;
+            }
+            let first = node.children[..i].iter().find(|ch| ch.name == child.name);
+            if let Some(first) = first {
+                report.errors.push(child.error(format!(
+                    "duplicate key {:?} (first defined on {})",
+                    child.name, first.span
+                )));
+            }
+        }
+
+        for field in self.fields {
+            if node.children.iter().any(|ch| ch.name == field.name) {
+                continue;
+            }
+            match field.presence {
+                Presence::Required => {
+                    let message = format!("missing required key {:?}", field.name);
+                    report.errors.push(node.error(message));
+                }
+                Presence::Default(values) => {
+                    let child = Node {
+                        name: field.name.to_owned(),
+                        values: values.iter().map(|&x| Value::Number(x)).collect(),
+                        children: vec![],
+                        comments: vec![],
+                        span: node.span.clone(),
+                        path: format!("{} > {}", node.path, field.name),
+                    };
+                    node.children.push(child);
+                }
+            }
+        }
+
+        for child in &mut node.children {
+            if let Some(field) = self.field(&child.name) {
+                field.ty.validate(child, report);
+            }
+        }
+    }
+}
+
+impl FieldType {
+    pub(super) fn validate(self, node: &mut Node, report: &mut Report) {
+        match self {
+            Self::Value(check) => {
+                if let Err(e) = check(node) {
+                    report.errors.push(e);
+                }
+            }
+            Self::Block(schema) => schema.validate(node, report),
+            Self::Objects => validate_objects(node, report),
+        }
+    }
+}
+
+fn validate_objects(node: &mut Node, report: &mut Report) {
+    if !node.values.is_empty() {
+        report.errors.push(node.error(format!(
+            "expected a block with no values, found {} values",
+            node.values.len()
+        )));
+    }
+    let registry = registry::registered();
+    for child in &mut node.children {
+        match registry.field_type(&child.name) {
+            Some(ty) => ty.validate(child, report),
+            None => report.errors.push(registry.unknown(child)),
+        }
+    }
+}
+
+/// Appends "did you mean ...?" to `message`, if one of the `candidates` looks
+/// like what `name` was meant to be.
+pub(super) fn with_suggestion<'a>(
+    message: String,
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> String {
+    let max_distance = (name.chars().count() / 3).max(1);
+    let closest = candidates
+        .into_iter()
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|&(d, _)| d <= max_distance)
+        .min();
+    match closest {
+        Some((_, c)) => format!("{message} (did you mean {c:?}?)"),
+        None => message,
+    }
+}
+
+/// Levenshtein distance, counted in chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitute = prev[j] + usize::from(ca != cb);
+            curr.push(substitute.min(prev[j + 1] + 1).min(curr[j] + 1));
+        }
+        prev = curr;
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENE_TEXT: &str = "
+scene {
+    camera {
+        position 0 0 -20
+    }
+    focal-distance 10
+    screen {
+        width 64
+        height 48
+    }
+    lights {
+        light {
+            position 5 0 -20
+        }
+    }
+    objects {
+        sphere {
+            center 0 0 0
+            radius 1
+        }
+    }
+}";
+
+    fn check(text: &str) -> Result<Vec<NodeError>, ValidationError> {
+        let mut tree: Node = text.parse().unwrap();
+        validate(&mut tree)
+    }
+
+    fn messages(errors: &[NodeError]) -> Vec<String> {
+        errors.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn valid_scene() {
+        assert!(check(SCENE_TEXT).unwrap().is_empty());
+    }
+
+    #[test]
+    fn fills_defaults() {
+        let mut tree: Node = SCENE_TEXT.parse().unwrap();
+        validate(&mut tree).unwrap();
+        let up = tree.get_path("camera up").unwrap();
+        let numbers = |xs: [f64; 3]| xs.map(Value::Number);
+        assert_eq!(up.values, numbers([0., 1., 0.]));
+        let color = tree.get_path("objects sphere color").unwrap();
+        assert_eq!(color.values, numbers([255., 255., 255.]));
+        assert_eq!(color.path, "scene > objects > sphere > color");
+    }
+
+    #[test]
+    fn unknown_key_warns_with_suggestion() {
+        let text = SCENE_TEXT.replace("radius 1", "radius 1\n            raduis 2");
+        let warnings = check(&text).unwrap();
+        assert_eq!(
+            messages(&warnings),
+            ["scene > objects > sphere > raduis (line 20): \
+              unknown key \"raduis\" (did you mean \"radius\"?)"]
+        );
+    }
+
+    #[test]
+    fn unknown_object_type_suggests() {
+        let text = SCENE_TEXT.replace("sphere {", "sphre {");
+        let errors = check(&text).unwrap_err().0;
+        assert_eq!(
+            messages(&errors),
+            ["scene > objects > sphre (line 17): \
+              unknown object type \"sphre\" (did you mean \"sphere\"?)"]
+        );
+    }
+
+    #[test]
+    fn duplicate_key() {
+        let text = SCENE_TEXT.replace("radius 1", "radius 1\n            radius 2");
+        let errors = check(&text).unwrap_err().0;
+        assert_eq!(
+            messages(&errors),
+            ["scene > objects > sphere > radius[1] (line 20): \
+              duplicate key \"radius\" (first defined on line 19)"]
+        );
+    }
+
+    #[test]
+    fn reports_all_errors() {
+        let text = SCENE_TEXT
+            .replace("            radius 1\n", "")
+            .replace("height 48", "height 4.8");
+        let errors = check(&text).unwrap_err().0;
+        assert_eq!(
+            messages(&errors),
+            [
+                "scene > screen > height (line 9): \
+                 cannot convert to u32. value has fractional component: 4.8",
+                "scene > objects > sphere (line 17): missing required key \"radius\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("radius", "radius"), 0);
+        assert_eq!(edit_distance("sphre", "sphere"), 1);
+        assert_eq!(edit_distance("raduis", "radius"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+}
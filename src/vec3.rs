@@ -54,6 +54,13 @@ impl Vec3 {
         self.norm_squared().sqrt()
     }
 
+    #[must_use]
+    pub fn cross_product(self, other: Self) -> Self {
+        let [ax, ay, az] = self.coords;
+        let [bx, by, bz] = other.coords;
+        Self::new(ay * bz - az * by, az * bx - ax * bz, ax * by - ay * bx)
+    }
+
     pub fn direct_product(self, other: Self) -> Self {
         let x = self.x() * other.x();
         let y = self.y() * other.y();